@@ -1,11 +1,30 @@
-use std::{collections::HashMap, fmt::Debug, sync::Mutex};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::Debug,
+    sync::{Arc, RwLock},
+};
 
 use anyhow::{bail, Result};
-use fastembed::TextEmbedding;
+use rusqlite::OptionalExtension;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 use zerocopy::IntoBytes;
 
+use crate::embedding::{Embedder, ImageEmbedder};
+
+mod attribute;
+mod backup;
+mod check;
+mod csv;
+mod migrations;
+mod photo_store;
+mod pool;
+mod vector_index;
+
+pub use attribute::{parse_filter, Attribute, AttributeType};
+pub use check::Repair;
+use vector_index::{VectorIndex, VectorIndexes};
+
 #[derive(Debug, Serialize)]
 pub struct ItemResult {
     pub id: i64,
@@ -14,9 +33,179 @@ pub struct ItemResult {
     pub similarity: f64,
     pub container_name: String,
     pub container_id: i64,
+    /// Where the container is kept, e.g. "Garage, left wall"
+    pub container_location: Option<String>,
+    /// The containers from ROOT down to the item's container
+    pub container_path: Vec<Breadcrumb>,
+    /// In a recursive container listing, the containers from the listed one down to the
+    /// item's, e.g. "Shelf 2 › Red box". `None` when the item is directly in it.
+    pub relative_path: Option<String>,
+    pub quantity: i64,
+    pub unit: Option<String>,
+    pub tags: Vec<String>,
+    pub attributes: Vec<Attribute>,
+    /// For the thumbnail's URL, `None` for items added without a photo
+    pub primary_photo_id: Option<i64>,
+}
+
+/// One of an item's photos
+#[derive(Debug, Serialize)]
+pub struct ItemPhoto {
+    pub id: i64,
+    /// The photo shown as the item's thumbnail and used for photo search
+    pub primary: bool,
+}
+
+/// A photo at the sizes the importer stores, encoded as JPEG
+#[derive(Debug)]
+pub struct NewPhoto {
+    pub small: Vec<u8>,
+    pub large: Vec<u8>,
+}
+
+/// The container a new item was meant for doesn't exist or is in the trash
+#[derive(Debug)]
+pub struct ContainerUnavailable(pub i64);
+
+impl std::fmt::Display for ContainerUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Container {} doesn't exist or is in the trash", self.0)
+    }
+}
+
+impl std::error::Error for ContainerUnavailable {}
+
+/// A container can't go inside itself or one of the containers nested in it
+#[derive(Debug)]
+pub struct ContainerCycle(pub i64);
+
+impl std::fmt::Display for ContainerCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Container {} can't be moved into itself or a container inside it",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ContainerCycle {}
+
+/// What a new item starts out with besides its name, description and photos
+#[derive(Debug)]
+pub struct ItemDetails {
+    pub quantity: i64,
+    pub unit: Option<String>,
+    /// Invalid attributes are skipped with a warning rather than failing the insert
+    pub attributes: Vec<Attribute>,
+}
+
+impl Default for ItemDetails {
+    fn default() -> Self {
+        Self {
+            quantity: 1,
+            unit: None,
+            attributes: Vec::new(),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum PhotoSize {
+    Small,
+    Large,
+}
+
+impl PhotoSize {
+    fn column(&self) -> &'static str {
+        match self {
+            PhotoSize::Small => "small_photo_hash",
+            PhotoSize::Large => "large_photo_hash",
+        }
+    }
+}
+
+/// A photo file in the photo store
+#[derive(Debug)]
+pub struct Photo {
+    /// SHA-256 of the contents, which never change for a given hash
+    pub hash: String,
+    pub path: std::path::PathBuf,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SearchOptions {
+    /// Semantic matches scoring below this (0.0 - 1.0) are dropped
+    pub min_similarity: f64,
+    /// Only return items inside this container or one of its descendants
+    pub container_id: Option<i64>,
+    /// Only return items carrying every one of these tags
+    pub tags: Vec<String>,
+    /// Only return items with a matching attribute for every `(key, value)`
+    pub attributes: Vec<(String, String)>,
+}
+
+/// A newly imported item that looks like something already in the inventory
+#[derive(Debug, Serialize)]
+pub struct PossibleDuplicate {
+    pub id: i64,
+    pub similarity: f64,
+    pub item: ItemResult,
+    pub duplicate_of: ItemResult,
+}
+
+/// A container whose name or location matched a search
 #[derive(Debug, Serialize)]
+pub struct ContainerResult {
+    pub id: i64,
+    pub name: String,
+    pub location: Option<String>,
+    pub path: Vec<Breadcrumb>,
+}
+
+/// A recorded change to an item. The name is kept as it was so deleted items still read well.
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub item_id: i64,
+    pub item_name: String,
+    /// create, edit, move, delete or restore
+    pub action: String,
+    /// Which field an edit changed
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    /// UTC, as `YYYY-MM-DD HH:MM:SS`
+    pub timestamp: String,
+    /// Whether the item still exists
+    pub exists: bool,
+}
+
+/// Something deleted and waiting in the trash. Deleting a container trashes its whole
+/// subtree as one entry.
+#[derive(Debug, Serialize)]
+pub struct TrashEntry {
+    pub id: i64,
+    /// item or container
+    pub kind: String,
+    pub target_id: i64,
+    pub name: String,
+    /// Where it was deleted from
+    pub location: String,
+    /// UTC, as `YYYY-MM-DD HH:MM:SS`
+    pub deleted_at: String,
+    /// Items that come back on restore
+    pub item_count: i64,
+}
+
+/// One container along a path from ROOT
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ContainerTree {
     pub id: i64,
     pub name: String,
@@ -24,9 +213,57 @@ pub struct ContainerTree {
     pub containers: Vec<ContainerTree>,
 }
 
+/// How many levels of nesting recursive container queries follow. Only a container cycle
+/// gets anywhere near it.
+const MAX_CONTAINER_DEPTH: i64 = 64;
+
+/// Orders a container's items can be listed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ItemSort {
+    #[default]
+    Name,
+    /// Newest first
+    Added,
+    /// Most recently changed first
+    Modified,
+}
+
+impl ItemSort {
+    pub fn from_name(name: &str) -> Result<ItemSort> {
+        Ok(match name {
+            "name" => ItemSort::Name,
+            "added" => ItemSort::Added,
+            "modified" => ItemSort::Modified,
+            _ => bail!("Unknown sort: {name}"),
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemSort::Name => "name",
+            ItemSort::Added => "added",
+            ItemSort::Modified => "modified",
+        }
+    }
+
+    /// Ends in `id` so pages don't overlap when the sort keys tie
+    fn order_by(&self) -> &'static str {
+        match self {
+            ItemSort::Name => "name COLLATE NOCASE, id",
+            ItemSort::Added => "created_at DESC, id DESC",
+            ItemSort::Modified => "modified_at DESC, id DESC",
+        }
+    }
+}
+
 pub struct Database {
-    conn: std::sync::Mutex<rusqlite::Connection>,
-    model: TextEmbedding,
+    pool: pool::Pool,
+    photos: photo_store::PhotoStore,
+    /// The configured embedder, which re-indexing builds new vector indexes with
+    embedder: Arc<dyn Embedder>,
+    indexes: RwLock<VectorIndexes>,
+    /// Produces the per-item photo vectors in `vec_item_images`
+    image_embedder: Arc<dyn ImageEmbedder>,
 }
 
 impl Debug for Database {
@@ -36,14 +273,15 @@ impl Debug for Database {
 }
 
 impl Database {
-    #[tracing::instrument]
-    pub fn init() -> Result<Self> {
-        unsafe {
-            #[allow(clippy::missing_transmute_annotations)]
-            rusqlite::ffi::sqlite3_auto_extension(Some(std::mem::transmute(
-                sqlite_vec::sqlite3_vec_init as *const (),
-            )));
-        }
+    /// `load_embedder` is only used to keep searching an older model's vectors when `embedder`
+    /// is a different model and the inventory has yet to be re-indexed.
+    #[tracing::instrument(skip(embedder, load_embedder, image_embedder))]
+    pub fn init(
+        embedder: Arc<dyn Embedder>,
+        load_embedder: impl Fn(&str) -> Result<Arc<dyn Embedder>>,
+        image_embedder: Arc<dyn ImageEmbedder>,
+    ) -> Result<Self> {
+        register_sqlite_vec();
 
         let db_name = if std::env::var("DEMO").is_ok() {
             if std::fs::exists("storage.demo.db").unwrap() {
@@ -61,96 +299,11 @@ impl Database {
         #[cfg(not(feature = "docker"))]
         let base_path = std::path::Path::new(".");
 
-        let conn = if let Ok(conn) = rusqlite::Connection::open_with_flags(
-            base_path.join(db_name),
-            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        ) {
-            conn
-        } else {
-            let conn = rusqlite::Connection::open(base_path.join(db_name))?;
-
-            conn.execute(
-                "CREATE VIRTUAL TABLE vec_items USING vec0(embedding float[1024])",
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE TABLE "Items" (
-                            "id"	INTEGER NOT NULL UNIQUE,
-                            "name"	TEXT NOT NULL,
-                            "description"	TEXT NOT NULL,
-                            "small_photo"	BLOB NOT NULL,
-                            "large_photo"	BLOB NOT NULL,
-                            "contained_by"  INTEGER NOT NULL,
-                            PRIMARY KEY("id" AUTOINCREMENT)
-                        )"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE TABLE "containers" (
-                            "id"	INTEGER NOT NULL UNIQUE,
-                            "name"	TEXT NOT NULL,
-                            "location"	TEXT,
-                            "contained_by"	INTEGER,
-                            PRIMARY KEY("id" AUTOINCREMENT)
-                        )"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE TABLE "embedding_to_item" (
-                            "id"	INTEGER NOT NULL UNIQUE,
-                            "embedding_id"	INTEGER NOT NULL,
-                            "item_id"	INTEGER NOT NULL,
-                            PRIMARY KEY("id" AUTOINCREMENT)
-                        )"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE TABLE "import_log" (
-                            "id"	INTEGER NOT NULL UNIQUE,
-                            "source"	TEXT NOT NULL,
-                            "status"	TEXT NOT NULL,
-                            "target_container"	INTEGER NOT NULL,
-                            PRIMARY KEY("id" AUTOINCREMENT)
-                        );"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE INDEX "idx_item_container" ON "Items" (
-                            "contained_by"
-                        );"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE INDEX "idx_container_container" ON "containers" (
-                            "contained_by"
-                        );"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE INDEX "idx_embedding_to_item_item_id" ON "embedding_to_item" (
-                            "item_id"
-                        )"#,
-                [],
-            )?;
-
-            conn.execute(
-                r#"CREATE INDEX "idx_embedding_to_item_embedding_id" ON "embedding_to_item" (
-                            "embedding_id"
-                        )"#,
-                [],
-            )?;
-
-            conn.execute(r#"INSERT INTO containers(id, name) VALUES (1, "ROOT")"#, [])?;
-
-            conn
-        };
+        let path = base_path.join(db_name);
+        let pool = pool::Pool::open(&path)?;
+        let photos = photo_store::PhotoStore::open(base_path.join("photos"))?;
+        let mut conn = pool.write();
+        let schema_version = migrations::run(&mut conn, &photos)?;
 
         let (sqlite_version, vec_version): (String, String) = conn
             .query_row("select sqlite_version(), vec_version()", [], |x| {
@@ -158,171 +311,406 @@ impl Database {
             })
             .unwrap();
 
-        info!("sqlite_version={sqlite_version}, vec_version={vec_version}");
+        info!("sqlite_version={sqlite_version}, vec_version={vec_version}, schema_version={schema_version}");
 
-        let fastembed_opts =
-            fastembed::InitOptions::new(fastembed::EmbeddingModel::MxbaiEmbedLargeV1);
+        let indexes = vector_index::load(&conn, embedder.clone(), load_embedder)?;
+        info!(
+            "embedding_model={}, dimension={}, vec_table={}",
+            indexes.active.embedder.model_id(),
+            indexes.active.embedder.dimension(),
+            indexes.active.vec_table
+        );
 
-        #[cfg(feature = "docker")]
-        let fastembed_opts = fastembed_opts.with_cache_dir(std::path::PathBuf::from("/cache"));
+        prepare_image_index(&conn, image_embedder.as_ref())?;
+        drop(conn);
 
-        let model = TextEmbedding::try_new(fastembed_opts)?;
+        let readers = std::thread::available_parallelism().map_or(4, |n| n.get());
+        pool.open_readers(&path, readers)?;
 
         Ok(Self {
-            conn: Mutex::new(conn),
-            model,
+            pool,
+            photos,
+            embedder,
+            indexes: RwLock::new(indexes),
+            image_embedder,
         })
     }
 
-    #[tracing::instrument(skip(description_statements))]
-    fn insert_embeddings(
+    /// Insert an item, its first photo becoming the primary one. Fails with
+    /// `ContainerUnavailable` unless `contained_by` is a live container.
+    #[tracing::instrument(skip(photos))]
+    pub fn insert_item(
         &self,
         name: &str,
-        description_statements: &[&str],
-        item_id: i64,
-    ) -> Result<()> {
-        let mut embedding_docs = vec![name];
-        embedding_docs.extend_from_slice(description_statements);
+        description: &[String],
+        photos: &[NewPhoto],
+        contained_by: i64,
+        details: &ItemDetails,
+    ) -> Result<i64> {
+        let indexes = self.indexes.read().unwrap();
+        let description_statements: Vec<&str> = description.iter().map(String::as_str).collect();
+        let embeddings = embed_item(&indexes, name, &description_statements)?;
+        let image_embedding = match photos.first() {
+            Some(primary) => Some(self.embed_photo(&primary.large)?),
+            None => None,
+        };
+        let photo_hashes = self.store_photos(photos)?;
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        ensure_container_live(&tx, contained_by)?;
+        tx.prepare(
+            r#"INSERT INTO 
+                    Items(name, description, contained_by, quantity, unit, created_at, modified_at)
+                    VALUES (?,?,?,?,?,CURRENT_TIMESTAMP,CURRENT_TIMESTAMP)"#,
+        )?
+        .execute(rusqlite::params![
+            name,
+            description.join("\n"),
+            contained_by,
+            details.quantity.max(0),
+            details.unit
+        ])?;
+        let item_id = tx.last_insert_rowid();
 
-        let full_description = description_statements.join("\n");
-        embedding_docs.push(&full_description);
-        let embeddings = self.model.embed(embedding_docs, None)?;
+        for attribute in &details.attributes {
+            if let Err(e) = insert_attribute(&tx, item_id, attribute) {
+                warn!("Skipping attribute {:?}: {}", attribute, e);
+            }
+        }
 
-        let conn = self.conn.lock().unwrap();
-        for embedding in embeddings {
-            conn.prepare("INSERT INTO vec_items(embedding) VALUES (?)")?
-                .execute(rusqlite::params![embedding.as_bytes()])?;
-            let embedding_id = conn.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO items_fts(rowid, name, description) VALUES (?,?,?)",
+            rusqlite::params![item_id, name, description.join("\n")],
+        )?;
 
-            conn.execute(
-                "INSERT INTO embedding_to_item(embedding_id, item_id) VALUES(?,?)",
-                [embedding_id, item_id],
-            )?;
+        for (index, embeddings) in &embeddings {
+            insert_embeddings(&tx, item_id, index, embeddings)?;
         }
+        insert_photos(&tx, item_id, &photo_hashes)?;
+        replace_image_embedding(&tx, item_id, image_embedding.as_deref())?;
 
-        Ok(())
+        let location = container_path_text(&tx, contained_by)?;
+        log_history(&tx, item_id, "create", None, None, Some(&location))?;
+
+        tx.commit()?;
+
+        Ok(item_id)
     }
 
-    #[tracing::instrument(skip(small_photo, large_photo))]
-    pub fn insert_item(
-        &self,
-        name: &str,
-        description: &[String],
-        small_photo: &[u8],
-        large_photo: &[u8],
-        contained_by: i64,
-    ) -> Result<()> {
-        let item_id = {
-            let conn: std::sync::MutexGuard<'_, rusqlite::Connection> = self.conn.lock().unwrap();
+    #[tracing::instrument]
+    pub fn query(&self, query: &str, options: &SearchOptions) -> Result<Vec<ItemResult>> {
+        let start = std::time::Instant::now();
+        let indexes = self.indexes.read().unwrap();
+        let index = &indexes.active;
+        let query_embedding = index.embedder.embed_query(query)?;
+
+        debug!(
+            "Query embedding generated in: {}ms",
+            std::time::Instant::now().duration_since(start).as_millis()
+        );
+
+        let scope = self.search_scope(options)?;
+        let window = scope.window();
+
+        let conn = self.pool.read();
+        let embedding_result = nearest_items(&conn, index, &query_embedding, window)?;
+
+        // results are ordered by distance, so the first hit for an item is its best
+        let mut item_hits: HashMap<i64, (f64, i64)> = HashMap::new();
+        for (item_id, contained_by, distance) in embedding_result {
+            if scope.contains(item_id, contained_by) {
+                item_hits.entry(item_id).or_insert((distance, 0)).1 += 1;
+            }
+        }
+
+        let similarities: HashMap<i64, f64> = item_hits
+            .into_iter()
+            .map(|(item_id, (best_distance, hits))| {
+                (item_id, similarity_score(best_distance, hits))
+            })
+            .collect();
+        let mut vector_ids: Vec<i64> = similarities.keys().copied().collect();
+        vector_ids.sort_by(|a, b| similarities[b].total_cmp(&similarities[a]));
+
+        let mut keyword_ids: Vec<i64> = if let Some(fts_query) = fts_query(query, false) {
             conn.prepare(
-                r#"INSERT INTO 
-                        Items(name, description, small_photo, large_photo, contained_by)
-                        VALUES (?,?,?,?,?)"#,
+                r#"
+                    SELECT
+                        a.rowid,
+                        b.contained_by
+                    FROM items_fts a JOIN Items b ON a.rowid = b.id
+                    WHERE items_fts MATCH ?1 AND b.trash_id IS NULL
+                    ORDER BY a.rank
+                    LIMIT ?2
+                    "#,
             )?
-            .execute(rusqlite::params![
-                name,
-                description.join("\n"),
-                small_photo.as_bytes(),
-                large_photo.as_bytes(),
-                contained_by
-            ])?;
-
-            conn.last_insert_rowid()
+            .query_map(rusqlite::params![fts_query, window], |r| {
+                anyhow::Result::Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|(item_id, contained_by)| scope.contains(*item_id, *contained_by))
+            .map(|(item_id, _)| item_id)
+            .take(SEARCH_WINDOW as usize)
+            .collect()
+        } else {
+            Vec::new()
         };
 
-        self.insert_embeddings(
-            name,
-            &description
-                .iter()
-                .map(String::as_str)
-                .collect::<Vec<&str>>(),
-            item_id,
-        )?;
+        // single prefixes like "a" match most items, so only hits on every term of the
+        // query skip the cutoff
+        let all_terms_ids = matching_all_terms(&conn, query, &keyword_ids)?;
+        let passes_cutoff = |item_id: &i64| {
+            all_terms_ids.contains(item_id)
+                || similarities
+                    .get(item_id)
+                    .is_some_and(|similarity| *similarity >= options.min_similarity)
+        };
+        vector_ids.retain(passes_cutoff);
+        keyword_ids.retain(passes_cutoff);
 
-        Ok(())
+        let item_ids = reciprocal_rank_fusion(&[&vector_ids, &keyword_ids]);
+
+        search_results(&conn, item_ids, similarities)
     }
 
-    #[tracing::instrument]
-    pub fn query(&self, query: &str) -> Result<Vec<ItemResult>> {
+    /// Find items that look like the one in `photo`
+    #[tracing::instrument(skip(photo))]
+    pub fn query_image(&self, photo: &[u8], options: &SearchOptions) -> Result<Vec<ItemResult>> {
         let start = std::time::Instant::now();
-        let query_embedding = self
-            .model
-            .embed(
-                vec![format!(
-                    "Represent this sentence for searching relevant passages: {query}"
-                )],
-                None,
-            )?
-            .pop()
-            .unwrap();
+        let Some(query_embedding) = self.image_embedder.embed_images(&[photo])?.pop() else {
+            bail!("No embedding generated for photo");
+        };
 
         debug!(
-            "Query embedding generated in: {}ms",
+            "Image embedding generated in: {}ms",
             std::time::Instant::now().duration_since(start).as_millis()
         );
 
-        let conn = self.conn.lock().unwrap();
-        let embedding_result: Vec<(i64, f64)> = conn
+        let scope = self.search_scope(options)?;
+
+        let conn = self.pool.read();
+        let image_result: Vec<(i64, f64)> = conn
             .prepare(
                 r#"
                     SELECT
                         rowid,
                         distance
-                    FROM vec_items
+                    FROM vec_item_images
                     WHERE embedding MATCH ?1
+                        AND k = ?2
                     ORDER BY distance
-                    LIMIT 100
                     "#,
             )?
-            .query_map([query_embedding.as_bytes()], |r| {
-                anyhow::Result::Ok((r.get(0)?, r.get(1)?))
-            })?
+            .query_map(
+                rusqlite::params![query_embedding.as_bytes(), scope.window()],
+                |r| anyhow::Result::Ok((r.get(0)?, r.get(1)?)),
+            )?
             .collect::<Result<Vec<_>, _>>()?;
 
+        // one vector per item, so the rowid is the item id and there are no repeat hits
         let mut item_ids = Vec::new();
-        let mut item_hits = HashMap::new();
-        for (embedding_id, _distance) in embedding_result {
-            let item_id: i64 = conn.query_row(
-                "SELECT item_id FROM embedding_to_item WHERE embedding_id = ?",
-                [embedding_id],
-                |row| Ok(row.get(0)),
-            )??;
-            if !item_hits.contains_key(&item_id) {
+        let mut similarities = HashMap::new();
+        for (item_id, distance) in image_result {
+            let Some(contained_by): Option<i64> = conn
+                .query_row(
+                    "SELECT contained_by FROM Items WHERE id = ? AND trash_id IS NULL",
+                    [item_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+            else {
+                continue;
+            };
+            let similarity = similarity_score(distance, 1);
+            if scope.contains(item_id, contained_by) && similarity >= options.min_similarity {
                 item_ids.push(item_id);
+                similarities.insert(item_id, similarity);
             }
-            *item_hits.entry(item_id).or_insert(0) += 1;
         }
-        let mut item_hits: Vec<(i64, i64)> = item_hits.iter().map(|(k, v)| (*k, *v)).collect();
-        item_hits.sort_by(|a, b| a.1.cmp(&b.1).reverse());
+        item_ids.truncate(SEARCH_WINDOW as usize);
 
-        #[derive(Debug, Deserialize)]
-        struct QueryResult {
-            id: i64,
-            name: String,
-            description: String,
-            contained_by: i64,
-            container_name: String,
+        search_results(&conn, item_ids, similarities)
+    }
+
+    /// Existing items whose text embeds close to a new item's name or full description
+    #[tracing::instrument(skip(description))]
+    pub fn find_duplicates(
+        &self,
+        name: &str,
+        description: &[String],
+        min_similarity: f64,
+    ) -> Result<Vec<ItemResult>> {
+        let indexes = self.indexes.read().unwrap();
+        let index = &indexes.active;
+        let full_description = description.join("\n");
+        let embeddings = index.embedder.embed_documents(&[name, &full_description])?;
+
+        let conn = self.pool.read();
+        let mut similarities: HashMap<i64, f64> = HashMap::new();
+        for embedding in embeddings {
+            for (item_id, _, distance) in
+                nearest_items(&conn, index, &embedding, DUPLICATE_SEARCH_WINDOW)?
+            {
+                let similarity = similarity_score(distance, 1);
+                let best = similarities.entry(item_id).or_default();
+                *best = best.max(similarity);
+            }
         }
 
-        let mut item_results = Vec::new();
-        for item_id in item_ids {
-            let result: QueryResult = conn.query_row("SELECT a.id, a.name, a.description, a.contained_by, b.name as container_name FROM Items a JOIN containers b ON a.contained_by = b.id WHERE a.id = ?", [item_id], |row| Ok(serde_rusqlite::from_row(row).unwrap()))?;
-            item_results.push(ItemResult {
-                id: result.id,
-                name: result.name,
-                description: result.description,
-                similarity: 0.0,
-                container_name: result.container_name,
-                container_id: result.contained_by,
+        similarities.retain(|_, similarity| *similarity >= min_similarity);
+        let mut item_ids: Vec<i64> = similarities.keys().copied().collect();
+        item_ids.sort_by(|a, b| similarities[b].total_cmp(&similarities[a]));
+
+        search_results(&conn, item_ids, similarities)
+    }
+
+    #[tracing::instrument]
+    pub fn log_possible_duplicate(
+        &self,
+        import_id: i64,
+        item_id: i64,
+        duplicate_of: i64,
+        similarity: f64,
+    ) -> Result<()> {
+        self.pool.write().execute(
+            "INSERT INTO possible_duplicates(import_id, item_id, duplicate_of, similarity) VALUES (?,?,?,?)",
+            rusqlite::params![import_id, item_id, duplicate_of, similarity],
+        )?;
+
+        Ok(())
+    }
+
+    /// Flagged duplicates that have not been dismissed, newest first
+    #[tracing::instrument]
+    pub fn get_possible_duplicates(&self) -> Result<Vec<PossibleDuplicate>> {
+        let flagged: Vec<(i64, i64, i64, f64)> = self
+            .pool
+            .read()
+            .prepare(
+                r#"SELECT id, item_id, duplicate_of, similarity FROM possible_duplicates
+                    WHERE item_id NOT IN (SELECT id FROM Items WHERE trash_id IS NOT NULL)
+                        AND duplicate_of NOT IN (SELECT id FROM Items WHERE trash_id IS NOT NULL)
+                    ORDER BY id DESC"#,
+            )?
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut duplicates = Vec::new();
+        for (id, item_id, duplicate_of, similarity) in flagged {
+            duplicates.push(PossibleDuplicate {
+                id,
+                similarity,
+                item: self.get_item(item_id)?,
+                duplicate_of: self.get_item(duplicate_of)?,
             });
         }
 
-        Ok(item_results)
+        Ok(duplicates)
+    }
+
+    /// Mark a flagged pair as not actually duplicates
+    #[tracing::instrument]
+    pub fn dismiss_possible_duplicate(&self, id: i64) -> Result<()> {
+        self.pool
+            .write()
+            .execute("DELETE FROM possible_duplicates WHERE id = ?", [id])?;
+
+        Ok(())
+    }
+
+    fn search_scope(&self, options: &SearchOptions) -> Result<SearchScope> {
+        let containers = match options.container_id {
+            Some(container_id) => Some(self.get_container_subtree(container_id)?),
+            None => None,
+        };
+        let mut items = if options.tags.is_empty() {
+            None
+        } else {
+            Some(self.get_tagged_items(&options.tags)?)
+        };
+        for (key, value) in &options.attributes {
+            let matching = self.get_items_with_attribute(key, value)?;
+            items = Some(match items {
+                Some(items) => items.intersection(&matching).copied().collect(),
+                None => matching,
+            });
+        }
+
+        Ok(SearchScope { containers, items })
+    }
+
+    /// Compute image vectors for every item that doesn't have one yet, such as everything
+    /// imported before photo search existed or after the image model changed
+    #[tracing::instrument]
+    pub fn embed_missing_images(&self) -> Result<()> {
+        let mut last_item_id = 0;
+        loop {
+            let next: Option<(i64, String)> = self
+                .pool
+                .read()
+                .query_row(
+                    r#"SELECT a.id, b.large_photo_hash FROM Items a JOIN item_photos b ON b.id = (
+                            SELECT id FROM item_photos WHERE item_id = a.id ORDER BY position, id LIMIT 1
+                        )
+                        WHERE a.id > ? AND a.id NOT IN (SELECT rowid FROM vec_item_images)
+                        ORDER BY a.id LIMIT 1"#,
+                    [last_item_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            let Some((item_id, large_photo_hash)) = next else {
+                break;
+            };
+            last_item_id = item_id;
+
+            let embedded = self
+                .photos
+                .get(&large_photo_hash)
+                .and_then(|large_photo| self.insert_image_embedding(item_id, &large_photo));
+            if let Err(e) = embedded {
+                error!("Failed to embed photo of item {item_id}: {e}");
+            } else {
+                info!("Embedded photo of item {item_id}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_image_embedding(&self, item_id: i64, photo: &[u8]) -> Result<()> {
+        let embedding = self.embed_photo(photo)?;
+
+        replace_image_embedding(&self.pool.write(), item_id, Some(&embedding))
+    }
+
+    fn embed_photo(&self, photo: &[u8]) -> Result<Vec<f32>> {
+        let Some(embedding) = self.image_embedder.embed_images(&[photo])?.pop() else {
+            bail!("No embedding generated for photo");
+        };
+
+        Ok(embedding)
+    }
+
+    fn store_photos(&self, photos: &[NewPhoto]) -> Result<Vec<(String, String)>> {
+        photos
+            .iter()
+            .map(|photo| {
+                Ok((
+                    self.photos.put(&photo.small)?,
+                    self.photos.put(&photo.large)?,
+                ))
+            })
+            .collect()
     }
 
     #[tracing::instrument]
     pub fn log_new_import(&self, source: &str, status: &str, target_container: i64) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.write();
         let mut stmt = conn
             .prepare("INSERT INTO import_log(source, status, target_container) VALUES (?,?,?)")?;
         stmt.execute(rusqlite::params![
@@ -337,7 +725,7 @@ impl Database {
     #[tracing::instrument]
     pub fn cancel_import(&self, import_id: i64, reason: Option<&str>) -> Result<()> {
         let reason = reason.unwrap_or("FAILED");
-        self.conn.lock().unwrap().execute(
+        self.pool.write().execute(
             r#"UPDATE import_log SET status = ? where id = ?"#,
             rusqlite::params![reason, import_id],
         )?;
@@ -347,7 +735,7 @@ impl Database {
 
     #[tracing::instrument]
     pub fn update_import(&self, import_id: i64, status: &str) -> Result<()> {
-        self.conn.lock().unwrap().execute(
+        self.pool.write().execute(
             r#"UPDATE import_log SET status = ? where id = ?"#,
             rusqlite::params![status.as_bytes(), import_id],
         )?;
@@ -355,94 +743,246 @@ impl Database {
         Ok(())
     }
 
+    /// The item's primary photo as a thumbnail
     #[tracing::instrument]
-    pub fn get_small_image(&self, item_id: i64) -> Result<Vec<u8>> {
-        let image: Vec<u8> = self
-            .conn
-            .lock()
-            .unwrap()
-            .prepare("SELECT small_photo FROM Items where id = ?")?
-            .query_row([item_id], |row| Ok(row.get(0)))??;
+    pub fn get_small_image(&self, item_id: i64) -> Result<Option<Photo>> {
+        self.primary_photo(item_id, PhotoSize::Small)
+    }
+
+    /// The item's primary photo
+    #[tracing::instrument]
+    pub fn get_large_image(&self, item_id: i64) -> Result<Option<Photo>> {
+        self.primary_photo(item_id, PhotoSize::Large)
+    }
 
-        Ok(image)
+    /// `None` for items added without a photo
+    fn primary_photo(&self, item_id: i64, size: PhotoSize) -> Result<Option<Photo>> {
+        let hash: Option<String> = self
+            .pool
+            .read()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM item_photos WHERE item_id = ? ORDER BY position, id LIMIT 1",
+                    size.column()
+                ),
+                [item_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        hash.map(|hash| self.photo(hash)).transpose()
     }
 
+    /// One photo of an item by its own id. Its file never changes.
     #[tracing::instrument]
-    pub fn get_large_image(&self, item_id: i64) -> Result<Vec<u8>> {
-        let image: Vec<u8> = self
-            .conn
-            .lock()
-            .unwrap()
-            .prepare("SELECT large_photo FROM Items where id = ?")?
-            .query_row([item_id], |row| Ok(row.get(0)))??;
+    pub fn get_item_photo(&self, photo_id: i64, size: PhotoSize) -> Result<Photo> {
+        let hash: String = self.pool.read().query_row(
+            &format!("SELECT {} FROM item_photos WHERE id = ?", size.column()),
+            [photo_id],
+            |row| row.get(0),
+        )?;
 
-        Ok(image)
+        self.photo(hash)
     }
 
+    /// The item's photos, primary first
     #[tracing::instrument]
-    pub fn get_container_tree(&self) -> Result<ContainerTree> {
-        let mut containers: HashMap<i64, Vec<ContainerRow>> = HashMap::new();
-        let mut root = None;
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("SELECT id, name, location, contained_by FROM containers")?
-            .query_map([], |row| {
-                Ok(ContainerRow {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    location: row.get(2)?,
-                    contained_by: row.get(3)?,
-                })
-            })?
-            .for_each(|row| {
-                if let Ok(row) = row {
-                    if row.id == 1 {
-                        root = Some(row);
-                    } else if let Some(contained_by) = row.contained_by {
-                        containers.entry(contained_by).or_default().push(row)
-                    }
-                }
-            });
+    pub fn get_item_photos(&self, item_id: i64) -> Result<Vec<ItemPhoto>> {
+        let photo_ids = self
+            .pool
+            .read()
+            .prepare("SELECT id FROM item_photos WHERE item_id = ? ORDER BY position, id")?
+            .query_map([item_id], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let Some(root) = root else {
-            bail!("Failed to find ROOT container");
-        };
+        Ok(photo_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| ItemPhoto {
+                id,
+                primary: i == 0,
+            })
+            .collect())
+    }
 
-        let mut root = ContainerTree {
-            id: root.id,
-            name: root.name,
-            location: root.location,
-            containers: Vec::new(),
+    /// Attach more photos to an item after its current ones
+    #[tracing::instrument(skip(photos))]
+    pub fn add_item_photos(&self, item_id: i64, photos: &[NewPhoto]) -> Result<()> {
+        // an item without photos gets its first primary photo here
+        let image_embedding = match (photos.first(), self.get_item_photos(item_id)?.is_empty()) {
+            (Some(primary), true) => Some(self.embed_photo(&primary.large)?),
+            _ => None,
         };
+        let photo_hashes = self.store_photos(photos)?;
 
-        fill_tree(&mut root, &mut containers);
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        insert_photos(&tx, item_id, &photo_hashes)?;
+        if let Some(embedding) = &image_embedding {
+            replace_image_embedding(&tx, item_id, Some(embedding))?;
+        }
+        touch_item(&tx, item_id)?;
+        tx.commit()?;
 
-        Ok(root)
+        Ok(())
     }
 
+    /// Make a photo the item's primary one, which photo search then matches against
     #[tracing::instrument]
-    pub fn get_container_items(&self, container_id: i64) -> Result<Vec<ItemResult>> {
-        let mut item_results = Vec::new();
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("SELECT id, name, description FROM Items WHERE contained_by = ?")?
-            .query_map([container_id], |row| {
-                Ok(ItemResult {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    description: row.get(2)?,
-                    similarity: 0.0,
-                    container_name: String::new(),
-                    container_id,
-                })
-            })?
-            .for_each(|row| {
-                if let Ok(row) = row {
-                    item_results.push(row);
-                }
-            });
+    pub fn set_primary_photo(&self, item_id: i64, photo_id: i64) -> Result<()> {
+        let large_photo = self
+            .photos
+            .get(&self.get_item_photo(photo_id, PhotoSize::Large)?.hash)?;
+        let embedding = self.embed_photo(&large_photo)?;
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        tx.execute(
+            r#"UPDATE item_photos
+                SET position = (SELECT MIN(position) FROM item_photos WHERE item_id = ?1) - 1
+                WHERE id = ?2 AND item_id = ?1"#,
+            [item_id, photo_id],
+        )?;
+        replace_image_embedding(&tx, item_id, Some(&embedding))?;
+        touch_item(&tx, item_id)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Detach a photo from an item, deleting its file unless another item uses it too
+    #[tracing::instrument]
+    pub fn remove_item_photo(&self, item_id: i64, photo_id: i64) -> Result<()> {
+        let photos = self.get_item_photos(item_id)?;
+        let was_primary = photos.first().is_some_and(|photo| photo.id == photo_id);
+        // the next photo takes over as primary, so photo search needs its vector
+        let image_embedding = match photos.get(1) {
+            Some(next) if was_primary => {
+                let large_photo = self
+                    .photos
+                    .get(&self.get_item_photo(next.id, PhotoSize::Large)?.hash)?;
+                Some(self.embed_photo(&large_photo)?)
+            }
+            _ => None,
+        };
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let hashes: Vec<String> = tx
+            .prepare("SELECT small_photo_hash, large_photo_hash FROM item_photos WHERE id = ? AND item_id = ?")?
+            .query_row([photo_id, item_id], |row| Ok(vec![row.get(0)?, row.get(1)?]))?;
+        tx.execute("DELETE FROM item_photos WHERE id = ?", [photo_id])?;
+        if was_primary {
+            replace_image_embedding(&tx, item_id, image_embedding.as_deref())?;
+        }
+        touch_item(&tx, item_id)?;
+        let unused_photos = unused_photos(&tx, hashes)?;
+        tx.commit()?;
+
+        self.remove_photo_files(unused_photos);
+
+        Ok(())
+    }
+
+    fn remove_photo_files(&self, hashes: Vec<String>) {
+        for hash in hashes {
+            if let Err(e) = self.photos.remove(&hash) {
+                error!("Failed to delete photo {hash}: {e}");
+            }
+        }
+    }
+
+    fn photo(&self, hash: String) -> Result<Photo> {
+        let path = self.photos.path(&hash)?;
+        if !path.exists() {
+            bail!("Photo {hash} is missing from the photo store");
+        }
+
+        Ok(Photo { hash, path })
+    }
+
+    #[tracing::instrument]
+    pub fn get_container_tree(&self) -> Result<ContainerTree> {
+        container_tree(&self.pool.read())
+    }
+
+    /// One page of the live items directly in a container, or with `recursive` anywhere
+    /// below it, annotated with the path from the container to theirs
+    #[tracing::instrument]
+    pub fn get_container_items(
+        &self,
+        container_id: i64,
+        recursive: bool,
+        sort: ItemSort,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<ItemResult>> {
+        let conn = self.pool.read();
+        let mut item_results = conn
+            .prepare(&format!(
+                r#"
+                    WITH RECURSIVE subtree(container_id, path, depth) AS (
+                        SELECT ?1, '', 0
+                        UNION ALL
+                        SELECT c.id, CASE s.depth WHEN 0 THEN c.name ELSE s.path || ' › ' || c.name END, s.depth + 1
+                        FROM containers c JOIN subtree s ON c.contained_by = s.container_id
+                        WHERE ?2 AND c.trash_id IS NULL AND s.depth < {MAX_CONTAINER_DEPTH}
+                    ),
+                    -- a container cycle reaches containers more than once, keep the shortest path
+                    below(container_id, path, depth) AS (
+                        SELECT container_id, path, MIN(depth) FROM subtree GROUP BY container_id
+                    )
+                    SELECT id, name, description, quantity, unit, contained_by, path
+                    FROM Items JOIN below ON Items.contained_by = below.container_id
+                    WHERE trash_id IS NULL
+                    ORDER BY {} LIMIT ?3 OFFSET ?4
+                    "#,
+                sort.order_by()
+            ))?
+            .query_map(
+                rusqlite::params![container_id, recursive, limit, offset],
+                |row| {
+                    let relative_path: String = row.get(6)?;
+                    Ok(ItemResult {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        description: row.get(2)?,
+                        similarity: 0.0,
+                        container_name: String::new(),
+                        container_id: row.get(5)?,
+                        container_location: None,
+                        container_path: Vec::new(),
+                        relative_path: Some(relative_path).filter(|path| !path.is_empty()),
+                        quantity: row.get(3)?,
+                        unit: row.get(4)?,
+                        tags: Vec::new(),
+                        attributes: Vec::new(),
+                        primary_photo_id: None,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let item_ids: Vec<i64> = item_results.iter().map(|item| item.id).collect();
+        let mut tags = items_tags(&conn, &item_ids)?;
+        let mut attributes = items_attributes(&conn, &item_ids)?;
+        let primary_photos = items_primary_photos(&conn, &item_ids)?;
+
+        let mut containers: HashMap<i64, (Vec<Breadcrumb>, Option<String>)> = HashMap::new();
+        for item in item_results.iter_mut() {
+            let (path, location) = match containers.entry(item.container_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert((
+                    container_path(&conn, item.container_id)?,
+                    container_location(&conn, item.container_id)?,
+                )),
+            };
+            item.container_path = path.clone();
+            item.container_location = location.clone();
+            item.tags = tags.remove(&item.id).unwrap_or_default();
+            item.attributes = attributes.remove(&item.id).unwrap_or_default();
+            item.primary_photo_id = primary_photos.get(&item.id).copied();
+        }
 
         Ok(item_results)
     }
@@ -450,20 +990,93 @@ impl Database {
     #[tracing::instrument]
     pub fn get_container_name(&self, container_id: i64) -> Result<String> {
         let name: String = self
-            .conn
-            .lock()
-            .unwrap()
+            .pool
+            .read()
             .prepare("SELECT name FROM containers WHERE id = ?")?
             .query_row([container_id], |row| Ok(row.get(0)))??;
 
         Ok(name)
     }
 
+    /// The given container plus every container nested below it
+    #[tracing::instrument]
+    pub fn get_container_subtree(&self, container_id: i64) -> Result<HashSet<i64>> {
+        container_subtree(&self.pool.read(), container_id)
+    }
+
+    #[tracing::instrument]
+    pub fn get_container_location(&self, container_id: i64) -> Result<Option<String>> {
+        container_location(&self.pool.read(), container_id)
+    }
+
+    /// Set where a container is kept, `None` clearing it
+    #[tracing::instrument]
+    pub fn set_container_location(&self, location: Option<&str>, container_id: i64) -> Result<()> {
+        self.pool
+            .write()
+            .prepare("UPDATE containers SET location = ? WHERE id = ?")?
+            .execute(rusqlite::params![location, container_id])?;
+
+        Ok(())
+    }
+
+    /// Containers whose name or location contains every word of the query
+    #[tracing::instrument]
+    pub fn query_containers(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<ContainerResult>> {
+        let words: Vec<String> = query
+            .split_whitespace()
+            .map(|word| word.to_lowercase())
+            .collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let scope = match options.container_id {
+            Some(container_id) => Some(self.get_container_subtree(container_id)?),
+            None => None,
+        };
+
+        let conn = self.pool.read();
+        let candidates = conn
+            .prepare(
+                "SELECT id, name, location FROM containers WHERE trash_id IS NULL ORDER BY name",
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut results = Vec::new();
+        for (id, name, location) in candidates {
+            if scope.as_ref().is_some_and(|scope| !scope.contains(&id)) {
+                continue;
+            }
+            let text =
+                format!("{} {}", name, location.as_deref().unwrap_or_default()).to_lowercase();
+            if words.iter().all(|word| text.contains(word)) {
+                results.push(ContainerResult {
+                    id,
+                    path: container_path(&conn, id)?,
+                    name,
+                    location,
+                });
+            }
+        }
+
+        Ok(results)
+    }
+
     #[tracing::instrument]
     pub fn set_container_name(&self, container_name: &str, container_id: i64) -> Result<()> {
-        self.conn
-            .lock()
-            .unwrap()
+        self.pool
+            .write()
             .prepare("UPDATE containers SET name = ? WHERE id = ?")?
             .execute(rusqlite::params![container_name, container_id])?;
 
@@ -473,9 +1086,8 @@ impl Database {
     #[tracing::instrument]
     pub fn get_container_parent(&self, container_id: i64) -> Result<i64> {
         let parent: i64 = self
-            .conn
-            .lock()
-            .unwrap()
+            .pool
+            .read()
             .prepare("SELECT contained_by FROM containers WHERE id = ?")?
             .query_row([container_id], |row| Ok(row.get(0)))??;
 
@@ -483,48 +1095,54 @@ impl Database {
     }
 
     #[tracing::instrument]
-    pub fn get_container_children(&self, container_id: i64) -> Result<Vec<i64>> {
-        let mut children = Vec::new();
-        for child_row in serde_rusqlite::from_rows::<i64>(
-            self.conn
-                .lock()
-                .unwrap()
-                .prepare("SELECT id FROM containers WHERE contained_by = ?")?
-                .query([container_id])?,
-        )
-        .flatten()
-        {
-            children.push(child_row);
-        }
-
-        Ok(children)
-    }
-
-    #[tracing::instrument]
-    /// This will recursively delete all containers and items
+    /// Move a container, its sub-containers and all their items to the trash as one entry
     pub fn delete_container(&self, container_id: i64) -> Result<()> {
-        let mut containers = vec![container_id];
-        while let Some(cur_container_id) = containers.pop() {
-            for item in self.get_container_items(cur_container_id).unwrap() {
-                self.delete_item(item.id).unwrap();
+        if container_id == 1 {
+            bail!("The ROOT container can't be deleted");
+        }
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let subtree = container_subtree(&tx, container_id)?;
+        let (name, parent): (String, i64) = tx.query_row(
+            "SELECT name, contained_by FROM containers WHERE id = ?",
+            [container_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let location = container_path_text(&tx, parent)?;
+        tx.execute(
+            "INSERT INTO trash(kind, target_id, name, location) VALUES ('container', ?, ?, ?)",
+            rusqlite::params![container_id, name, location],
+        )?;
+        let trash_id = tx.last_insert_rowid();
+
+        for cur_container_id in subtree {
+            let item_ids = tx
+                .prepare("SELECT id FROM Items WHERE contained_by = ? AND trash_id IS NULL")?
+                .query_map([cur_container_id], |row| row.get::<_, i64>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let item_location = container_path_text(&tx, cur_container_id)?;
+            for item_id in item_ids {
+                log_history(&tx, item_id, "delete", None, Some(&item_location), None)?;
             }
 
-            containers.extend(self.get_container_children(cur_container_id).unwrap());
-
-            // Do deletion of this container, now that it has no items and we know its children
-            self.conn
-                .lock()
-                .unwrap()
-                .prepare("DELETE FROM containers WHERE id = ?")?
-                .execute([cur_container_id])?;
+            tx.execute(
+                "UPDATE Items SET trash_id = ? WHERE contained_by = ? AND trash_id IS NULL",
+                [trash_id, cur_container_id],
+            )?;
+            tx.execute(
+                "UPDATE containers SET trash_id = ? WHERE id = ?",
+                [trash_id, cur_container_id],
+            )?;
         }
 
+        tx.commit()?;
+
         Ok(())
     }
 
     #[tracing::instrument]
     pub fn add_child_container(&self, name: &str, parent_id: i64) -> Result<()> {
-        self.conn.lock().unwrap().execute(
+        self.pool.write().execute(
             "INSERT INTO containers(name, contained_by) VALUES (?,?)",
             rusqlite::params![name, parent_id,],
         )?;
@@ -532,13 +1150,46 @@ impl Database {
         Ok(())
     }
 
+    /// Put a container inside another. Fails with `ContainerUnavailable` unless the target
+    /// is a live container, and with `ContainerCycle` when it is the container itself or
+    /// nested inside it.
     #[tracing::instrument]
     pub fn move_container(&self, container_source_id: i64, container_target_id: i64) -> Result<()> {
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("UPDATE containers SET contained_by = ? where id = ?")?
-            .execute([container_target_id, container_source_id])?;
+        if container_source_id == 1 {
+            bail!("The ROOT container can't be moved");
+        }
+        if container_source_id == container_target_id {
+            return Err(ContainerCycle(container_source_id).into());
+        }
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        ensure_container_live(&tx, container_target_id)?;
+        let target_is_nested: bool = tx.query_row(
+            r#"
+                WITH RECURSIVE subtree(id) AS (
+                    SELECT id FROM containers WHERE contained_by = ?1
+                    UNION
+                    SELECT c.id FROM containers c JOIN subtree s ON c.contained_by = s.id
+                )
+                SELECT EXISTS(SELECT 1 FROM subtree WHERE id = ?2)
+                "#,
+            [container_source_id, container_target_id],
+            |row| row.get(0),
+        )?;
+        if target_is_nested {
+            return Err(ContainerCycle(container_source_id).into());
+        }
+
+        let moved = tx.execute(
+            "UPDATE containers SET contained_by = ? WHERE id = ?",
+            [container_target_id, container_source_id],
+        )?;
+        if moved == 0 {
+            bail!("Container {container_source_id} doesn't exist");
+        }
+
+        tx.commit()?;
 
         Ok(())
     }
@@ -552,13 +1203,16 @@ impl Database {
             description: String,
             contained_by: i64,
             container_name: String,
+            container_location: Option<String>,
+            quantity: i64,
+            unit: Option<String>,
         }
 
-        let result = self
-                .conn.lock().unwrap()
+        let result = self.pool.read()
                 .prepare(
-                    "SELECT a.id, a.name, a.description, a.contained_by, b.name as container_name FROM Items a JOIN containers b ON a.contained_by = b.id WHERE a.id = ?",
+                    "SELECT a.id, a.name, a.description, a.contained_by, b.name as container_name, b.location as container_location, a.quantity, a.unit FROM Items a JOIN containers b ON a.contained_by = b.id WHERE a.id = ?",
                 )?.query_row([item_id], |row| Ok(serde_rusqlite::from_row::<QueryResult>(row).unwrap()))?;
+        let container_path = container_path(&self.pool.read(), result.contained_by)?;
 
         Ok(ItemResult {
             id: result.id,
@@ -567,76 +1221,1297 @@ impl Database {
             similarity: 0.0,
             container_name: result.container_name,
             container_id: result.contained_by,
+            container_location: result.container_location,
+            container_path,
+            relative_path: None,
+            quantity: result.quantity,
+            unit: result.unit,
+            tags: self.get_item_tags(item_id)?,
+            attributes: self.get_item_attributes(item_id)?,
+            primary_photo_id: items_primary_photos(&self.pool.read(), &[item_id])?
+                .get(&item_id)
+                .copied(),
         })
     }
 
     #[tracing::instrument]
-    pub fn update_item(&self, item_id: i64, item_name: &str, item_description: &str) -> Result<()> {
-        self.delete_embeddings_for_item(item_id)?;
-
+    pub fn update_item(
+        &self,
+        item_id: i64,
+        item_name: &str,
+        item_description: &str,
+        quantity: i64,
+        unit: Option<&str>,
+    ) -> Result<()> {
+        let indexes = self.indexes.read().unwrap();
         let description_statements: Vec<&str> = item_description.split("\n").collect();
-        self.insert_embeddings(item_name, &description_statements, item_id)?;
+        let embeddings = embed_item(&indexes, item_name, &description_statements)?;
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        for (index, embeddings) in &embeddings {
+            delete_embeddings(&tx, item_id, index)?;
+            insert_embeddings(&tx, item_id, index, embeddings)?;
+        }
 
         // update item record
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("UPDATE Items SET name = ?, description = ? WHERE id = ?")?
+        let (old_name, old_description): (String, String) = tx.query_row(
+            "SELECT name, description FROM Items WHERE id = ?",
+            [item_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        tx.prepare("UPDATE Items SET name = ?, description = ? WHERE id = ?")?
+            .execute(rusqlite::params![item_name, item_description, item_id])?;
+        tx.prepare("UPDATE items_fts SET name = ?, description = ? WHERE rowid = ?")?
             .execute(rusqlite::params![item_name, item_description, item_id])?;
 
+        if old_name != item_name {
+            log_history(
+                &tx,
+                item_id,
+                "edit",
+                Some("name"),
+                Some(&old_name),
+                Some(item_name),
+            )?;
+        }
+        if old_description != item_description {
+            log_history(
+                &tx,
+                item_id,
+                "edit",
+                Some("description"),
+                Some(&old_description),
+                Some(item_description),
+            )?;
+        }
+
+        let old_quantity = quantity_text(&tx, item_id)?;
+        tx.prepare("UPDATE Items SET quantity = ?, unit = ? WHERE id = ?")?
+            .execute(rusqlite::params![quantity.max(0), unit, item_id])?;
+        log_quantity_change(&tx, item_id, &old_quantity)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Move an item to the trash
+    #[tracing::instrument]
+    pub fn delete_item(&self, item_id: i64) -> Result<()> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let (name, container_id): (String, i64) = tx.query_row(
+            "SELECT name, contained_by FROM Items WHERE id = ?",
+            [item_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let location = container_path_text(&tx, container_id)?;
+        log_history(&tx, item_id, "delete", None, Some(&location), None)?;
+
+        tx.execute(
+            "INSERT INTO trash(kind, target_id, name, location) VALUES ('item', ?, ?, ?)",
+            rusqlite::params![item_id, name, location],
+        )?;
+        tx.execute(
+            "UPDATE Items SET trash_id = ? WHERE id = ?",
+            [tx.last_insert_rowid(), item_id],
+        )?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Everything in the trash, most recently deleted first
+    #[tracing::instrument]
+    pub fn get_trash(&self) -> Result<Vec<TrashEntry>> {
+        Ok(self
+            .pool
+            .read()
+            .prepare(
+                r#"
+                    SELECT a.id, a.kind, a.target_id, a.name, a.location, a.deleted_at,
+                        (SELECT COUNT(*) FROM Items b WHERE b.trash_id = a.id)
+                    FROM trash a
+                    ORDER BY a.id DESC
+                    "#,
+            )?
+            .query_map([], |row| {
+                Ok(TrashEntry {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    target_id: row.get(2)?,
+                    name: row.get(3)?,
+                    location: row.get(4)?,
+                    deleted_at: row.get(5)?,
+                    item_count: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Put a trashed item or container subtree back where it was. If its original parent
+    /// is gone or in the trash itself, it goes to ROOT instead.
+    #[tracing::instrument]
+    pub fn restore_trash(&self, trash_id: i64) -> Result<()> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let (kind, target_id): (String, i64) = tx.query_row(
+            "SELECT kind, target_id FROM trash WHERE id = ?",
+            [trash_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let table = if kind == "container" {
+            "containers"
+        } else {
+            "Items"
+        };
+
+        let parent: i64 = tx.query_row(
+            &format!("SELECT contained_by FROM {table} WHERE id = ?"),
+            [target_id],
+            |row| row.get(0),
+        )?;
+        let parent_live = tx
+            .query_row(
+                "SELECT id FROM containers WHERE id = ? AND trash_id IS NULL",
+                [parent],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .is_some();
+        if !parent_live {
+            tx.execute(
+                &format!("UPDATE {table} SET contained_by = 1 WHERE id = ?"),
+                [target_id],
+            )?;
+        }
+
+        tx.execute(
+            "UPDATE containers SET trash_id = NULL WHERE trash_id = ?",
+            [trash_id],
+        )?;
+        let item_ids = tx
+            .prepare("SELECT id, contained_by FROM Items WHERE trash_id = ?")?
+            .query_map([trash_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        tx.execute(
+            "UPDATE Items SET trash_id = NULL WHERE trash_id = ?",
+            [trash_id],
+        )?;
+        for (item_id, container_id) in item_ids {
+            let location = container_path_text(&tx, container_id)?;
+            log_history(&tx, item_id, "restore", None, None, Some(&location))?;
+        }
+
+        tx.execute("DELETE FROM trash WHERE id = ?", [trash_id])?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Permanently delete everything in the trash deleted more than `retention_days` ago
+    #[tracing::instrument]
+    pub fn purge_trash(&self, retention_days: i64) -> Result<()> {
+        let expired = self
+            .pool
+            .write()
+            .prepare("SELECT id FROM trash WHERE deleted_at <= datetime('now', ?)")?
+            .query_map([format!("-{retention_days} days")], |row| {
+                row.get::<_, i64>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for trash_id in expired {
+            info!("Purging trash entry {trash_id}");
+            self.purge_trash_entry(trash_id)?;
+        }
+
         Ok(())
     }
 
+    /// Permanently delete one trash entry with all its containers, items, photos and vectors
     #[tracing::instrument]
-    fn delete_embeddings_for_item(&self, item_id: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        // get related embedding_ids
-        let mut embedding_ids = Vec::new();
-        for embedding_id in serde_rusqlite::from_rows::<i64>(
-            conn.prepare("SELECT embedding_id FROM embedding_to_item where item_id = ?")?
-                .query([item_id])?,
-        ) {
-            if let Ok(embedding_id) = embedding_id {
-                embedding_ids.push(embedding_id);
+    pub fn purge_trash_entry(&self, trash_id: i64) -> Result<()> {
+        let indexes = self.indexes.read().unwrap();
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let item_ids = tx
+            .prepare("SELECT id FROM Items WHERE trash_id = ?")?
+            .query_map([trash_id], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let hashes = tx
+            .prepare(
+                r#"SELECT small_photo_hash FROM item_photos WHERE item_id IN (SELECT id FROM Items WHERE trash_id = ?1)
+                    UNION SELECT large_photo_hash FROM item_photos WHERE item_id IN (SELECT id FROM Items WHERE trash_id = ?1)"#,
+            )?
+            .query_map([trash_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for item_id in item_ids {
+            for index in indexes.all() {
+                delete_embeddings(&tx, item_id, index)?;
             }
+            purge_item(&tx, item_id)?;
         }
+        delete_unused_tags(&tx)?;
+
+        tx.execute("DELETE FROM containers WHERE trash_id = ?", [trash_id])?;
+        tx.execute("DELETE FROM trash WHERE id = ?", [trash_id])?;
+
+        let unused_photos = unused_photos(&tx, hashes)?;
+        tx.commit()?;
+
+        self.remove_photo_files(unused_photos);
+
+        Ok(())
+    }
 
-        for embedding_id in embedding_ids {
-            // delete embedding
-            conn.prepare("DELETE FROM vec_items where rowid = ?")?
-                .execute(rusqlite::params![embedding_id])?;
+    /// Every tag in use, for suggestions and filtering
+    #[tracing::instrument]
+    pub fn get_tags(&self) -> Result<Vec<String>> {
+        Ok(self
+            .pool
+            .read()
+            .prepare("SELECT name FROM tags ORDER BY name")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    #[tracing::instrument]
+    pub fn get_item_tags(&self, item_id: i64) -> Result<Vec<String>> {
+        item_tags(&self.pool.read(), item_id)
+    }
+
+    /// Tag an item, creating the tag if it's new. Tags are trimmed and lowercased.
+    #[tracing::instrument]
+    pub fn add_item_tag(&self, item_id: i64, tag: &str) -> Result<()> {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() {
+            bail!("Tag must not be empty");
         }
 
-        conn.prepare("DELETE FROM embedding_to_item where item_id = ?")?
-            .execute(rusqlite::params![item_id])?;
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        tx.prepare("INSERT OR IGNORE INTO tags(name) VALUES (?)")?
+            .execute([&tag])?;
+        tx.prepare(
+            "INSERT OR IGNORE INTO item_tag(item_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+        )?
+        .execute(rusqlite::params![item_id, tag])?;
+        touch_item(&tx, item_id)?;
+
+        tx.commit()?;
 
         Ok(())
     }
 
     #[tracing::instrument]
-    pub fn delete_item(&self, item_id: i64) -> Result<()> {
-        self.delete_embeddings_for_item(item_id)?;
+    pub fn remove_item_tag(&self, item_id: i64, tag: &str) -> Result<()> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        tx.prepare(
+            "DELETE FROM item_tag WHERE item_id = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)",
+        )?
+        .execute(rusqlite::params![item_id, tag])?;
+        delete_unused_tags(&tx)?;
+        touch_item(&tx, item_id)?;
+
+        tx.commit()?;
 
-        // delete item
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("DELETE FROM Items where id = ?")?
-            .execute(rusqlite::params![item_id])?;
+        Ok(())
+    }
+
+    /// Items carrying every one of `tags`
+    #[tracing::instrument]
+    fn get_tagged_items(&self, tags: &[String]) -> Result<HashSet<i64>> {
+        let tags: HashSet<String> = tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+        let placeholders = vec!["?"; tags.len()].join(",");
+
+        Ok(self
+            .pool
+            .read()
+            .prepare(&format!(
+                r#"
+                    SELECT a.item_id
+                    FROM item_tag a JOIN tags b ON a.tag_id = b.id
+                    WHERE b.name IN ({placeholders})
+                    GROUP BY a.item_id
+                    HAVING COUNT(*) = {}
+                    "#,
+                tags.len()
+            ))?
+            .query_map(rusqlite::params_from_iter(&tags), |row| row.get(0))?
+            .collect::<Result<HashSet<_>, _>>()?)
+    }
+
+    #[tracing::instrument]
+    pub fn get_item_attributes(&self, item_id: i64) -> Result<Vec<Attribute>> {
+        item_attributes(&self.pool.read(), item_id)
+    }
+
+    /// Add an attribute to an item, replacing any existing value for the same key
+    #[tracing::instrument]
+    pub fn set_item_attribute(&self, item_id: i64, attribute: &Attribute) -> Result<()> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        insert_attribute(&tx, item_id, attribute)?;
+        touch_item(&tx, item_id)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument]
+    pub fn remove_item_attribute(&self, item_id: i64, key: &str) -> Result<()> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        tx.prepare("DELETE FROM item_attributes WHERE item_id = ? AND key = ?")?
+            .execute(rusqlite::params![item_id, key])?;
+        touch_item(&tx, item_id)?;
+
+        tx.commit()?;
 
         Ok(())
     }
 
+    /// Items with an attribute `key` whose value matches `value` according to its type
+    #[tracing::instrument]
+    fn get_items_with_attribute(&self, key: &str, value: &str) -> Result<HashSet<i64>> {
+        let conn = self.pool.read();
+        let mut items = HashSet::new();
+        let mut statement =
+            conn.prepare("SELECT item_id, value, value_type FROM item_attributes WHERE key = ?")?;
+        let mut rows = statement.query([key.trim().to_lowercase()])?;
+        while let Some(row) = rows.next()? {
+            let attribute = Attribute {
+                key: key.to_string(),
+                value: row.get(1)?,
+                value_type: AttributeType::from_name(&row.get::<_, String>(2)?),
+            };
+            if attribute.matches(value) {
+                items.insert(row.get(0)?);
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Add `delta` to the item's quantity, never going below zero. Returns the new quantity.
     #[tracing::instrument]
+    pub fn adjust_item_quantity(&self, item_id: i64, delta: i64) -> Result<i64> {
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let old_quantity = quantity_text(&tx, item_id)?;
+        tx.prepare("UPDATE Items SET quantity = MAX(quantity + ?, 0) WHERE id = ?")?
+            .execute([delta, item_id])?;
+        log_quantity_change(&tx, item_id, &old_quantity)?;
+        let quantity = tx.query_row(
+            "SELECT quantity FROM Items WHERE id = ?",
+            [item_id],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+
+        Ok(quantity)
+    }
+
+    #[tracing::instrument]
+    /// Fails with `ContainerUnavailable` unless `container_id` is a live container
     pub fn move_item(&self, item_id: i64, container_id: i64) -> Result<()> {
-        self.conn
-            .lock()
-            .unwrap()
-            .prepare("UPDATE Items SET contained_by = ? where id = ?")?
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        ensure_container_live(&tx, container_id)?;
+        let old_container_id: i64 = tx.query_row(
+            "SELECT contained_by FROM Items WHERE id = ?",
+            [item_id],
+            |row| row.get(0),
+        )?;
+        tx.prepare("UPDATE Items SET contained_by = ? where id = ?")?
             .execute([container_id, item_id])?;
 
+        if old_container_id != container_id {
+            let old_location = container_path_text(&tx, old_container_id)?;
+            let new_location = container_path_text(&tx, container_id)?;
+            log_history(
+                &tx,
+                item_id,
+                "move",
+                None,
+                Some(&old_location),
+                Some(&new_location),
+            )?;
+        }
+
+        tx.commit()?;
+
         Ok(())
     }
+
+    /// Changes to one item, newest first
+    #[tracing::instrument]
+    pub fn get_item_history(&self, item_id: i64) -> Result<Vec<HistoryEntry>> {
+        history_entries(
+            &self.pool.read(),
+            "WHERE a.item_id = ?1 ORDER BY a.id DESC",
+            item_id,
+        )
+    }
+
+    /// The latest changes across all items, newest first
+    #[tracing::instrument]
+    pub fn get_recent_changes(&self, limit: i64) -> Result<Vec<HistoryEntry>> {
+        history_entries(&self.pool.read(), "ORDER BY a.id DESC LIMIT ?1", limit)
+    }
+
+    /// Model the vectors being searched were produced by
+    pub fn active_embedding_model(&self) -> String {
+        self.indexes
+            .read()
+            .unwrap()
+            .active
+            .embedder
+            .model_id()
+            .to_string()
+    }
+
+    pub fn reindex_pending(&self) -> bool {
+        self.indexes.read().unwrap().pending.is_some()
+    }
+
+    /// Start building a fresh vector index with the configured embedder. Until `reindex`
+    /// finishes, searches use the current index and item writes go to both.
+    #[tracing::instrument]
+    pub fn begin_reindex(&self) -> Result<()> {
+        let mut indexes = self.indexes.write().unwrap();
+        if indexes.pending.is_some() {
+            bail!("Re-index already in progress");
+        }
+
+        let conn = self.pool.write();
+        indexes.pending = Some(vector_index::create(&conn, self.embedder.clone(), false)?);
+
+        Ok(())
+    }
+
+    /// Re-embed every item into the pending index in batches, calling `progress` with
+    /// (done, total) after each, then swap it in as the active index.
+    #[tracing::instrument(skip(progress))]
+    pub fn reindex(&self, progress: impl Fn(usize, usize)) -> Result<()> {
+        let total: usize = self
+            .pool
+            .read()
+            .query_row("SELECT COUNT(*) FROM Items", [], |row| row.get(0))?;
+
+        let mut done = 0;
+        let mut last_item_id = 0;
+        loop {
+            let batch: Vec<(i64, String, String)> = self
+                .pool
+                .read()
+                .prepare(
+                    "SELECT id, name, description FROM Items WHERE id > ? ORDER BY id LIMIT ?",
+                )?
+                .query_map([last_item_id, REINDEX_BATCH_SIZE], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<_, _>>()?;
+
+            let Some((batch_last_item_id, _, _)) = batch.last() else {
+                break;
+            };
+            last_item_id = *batch_last_item_id;
+
+            {
+                let indexes = self.indexes.read().unwrap();
+                let Some(pending) = &indexes.pending else {
+                    bail!("No re-index in progress");
+                };
+
+                for (item_id, name, description) in &batch {
+                    let description_statements: Vec<&str> = description.split("\n").collect();
+                    let embeddings = embed_documents(pending, name, &description_statements)?;
+
+                    let mut conn = self.pool.write();
+                    let tx = conn.transaction()?;
+                    // an edit while embedding already wrote fresh vectors to the pending index
+                    let current: Option<(String, String)> = tx
+                        .query_row(
+                            "SELECT name, description FROM Items WHERE id = ?",
+                            [item_id],
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )
+                        .optional()?;
+                    if current.as_ref() != Some(&(name.clone(), description.clone())) {
+                        debug!("Item {item_id} changed while re-indexing, keeping its new vectors");
+                        continue;
+                    }
+                    delete_embeddings(&tx, *item_id, pending)?;
+                    insert_embeddings(&tx, *item_id, pending, &embeddings)?;
+                    tx.commit()?;
+                }
+            }
+
+            // items added mid-way can push this past the initial count
+            done += batch.len();
+            progress(done, total.max(done));
+        }
+
+        let mut indexes = self.indexes.write().unwrap();
+        let Some(pending) = indexes.pending.clone() else {
+            bail!("No re-index in progress");
+        };
+        vector_index::activate(&self.pool.write(), &pending, &indexes.active)?;
+        info!(
+            "Re-index complete, now searching {} vectors in {}",
+            pending.embedder.model_id(),
+            pending.vec_table
+        );
+        indexes.active = pending;
+        indexes.pending = None;
+
+        Ok(())
+    }
+}
+
+/// Load sqlite-vec into every connection opened from now on
+fn register_sqlite_vec() {
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(|| unsafe {
+        #[allow(clippy::missing_transmute_annotations)]
+        rusqlite::ffi::sqlite3_auto_extension(Some(std::mem::transmute(
+            sqlite_vec::sqlite3_vec_init as *const (),
+        )));
+    });
+}
+
+/// Smoothing constant for reciprocal rank fusion, as used in the original RRF paper
+const RRF_K: f64 = 60.0;
+
+/// (Re)create `vec_item_images` if the image model changed, its vectors are then rebuilt by
+/// `Database::embed_missing_images`
+fn prepare_image_index(
+    conn: &rusqlite::Connection,
+    image_embedder: &dyn ImageEmbedder,
+) -> Result<()> {
+    let current: Option<(String, usize)> = conn
+        .query_row("SELECT model, dimension FROM image_index", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()?;
+    if current.as_ref().is_some_and(|(model, dimension)| {
+        model == image_embedder.model_id() && *dimension == image_embedder.dimension()
+    }) {
+        return Ok(());
+    }
+
+    info!("Creating image index for {}", image_embedder.model_id());
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DROP TABLE IF EXISTS vec_item_images", [])?;
+    tx.execute(
+        &format!(
+            "CREATE VIRTUAL TABLE vec_item_images USING vec0(embedding float[{}])",
+            image_embedder.dimension()
+        ),
+        [],
+    )?;
+    tx.execute("DELETE FROM image_index", [])?;
+    tx.execute(
+        "INSERT INTO image_index(model, dimension) VALUES (?,?)",
+        rusqlite::params![image_embedder.model_id(), image_embedder.dimension()],
+    )?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Nearest embeddings in `index` as (item id, item container, distance), closest first
+fn nearest_items(
+    conn: &rusqlite::Connection,
+    index: &VectorIndex,
+    embedding: &[f32],
+    k: i64,
+) -> Result<Vec<(i64, i64, f64)>> {
+    let embedding_result: Vec<(i64, f64)> = conn
+        .prepare(&format!(
+            r#"
+                SELECT
+                    rowid,
+                    distance
+                FROM {}
+                WHERE embedding MATCH ?1
+                    AND k = ?2
+                ORDER BY distance
+                "#,
+            index.vec_table
+        ))?
+        .query_map(rusqlite::params![embedding.as_bytes(), k], |r| {
+            anyhow::Result::Ok((r.get(0)?, r.get(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut items = Vec::new();
+    for (embedding_id, distance) in embedding_result {
+        let hit: Option<(i64, i64)> = conn
+            .query_row(
+                r#"SELECT a.item_id, b.contained_by
+                FROM embedding_to_item a JOIN Items b ON a.item_id = b.id
+                WHERE a.embedding_id = ? AND a.vector_index = ? AND b.trash_id IS NULL"#,
+                [embedding_id, index.id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        // trashed items keep their vectors so restoring them doesn't need a re-embed
+        if let Some((item_id, contained_by)) = hit {
+            items.push((item_id, contained_by, distance));
+        }
+    }
+
+    Ok(items)
+}
+
+/// A container's ancestors from ROOT down to and including the container. In a container
+/// cycle the walk stops at the first container it reaches twice.
+fn container_path(conn: &rusqlite::Connection, container_id: i64) -> Result<Vec<Breadcrumb>> {
+    Ok(conn
+        .prepare(&format!(
+            r#"
+                WITH RECURSIVE ancestors(id, name, contained_by, depth, visited) AS (
+                    SELECT id, name, contained_by, 0, ',' || id || ',' FROM containers WHERE id = ?
+                    UNION ALL
+                    SELECT c.id, c.name, c.contained_by, a.depth + 1, a.visited || c.id || ','
+                    FROM containers c JOIN ancestors a ON c.id = a.contained_by
+                    WHERE instr(a.visited, ',' || c.id || ',') = 0 AND a.depth < {MAX_CONTAINER_DEPTH}
+                )
+                SELECT id, name FROM ancestors ORDER BY depth DESC
+                "#
+        ))?
+        .query_map([container_id], |row| {
+            Ok(Breadcrumb {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+fn container_location(conn: &rusqlite::Connection, container_id: i64) -> Result<Option<String>> {
+    Ok(conn.query_row(
+        "SELECT location FROM containers WHERE id = ?",
+        [container_id],
+        |row| row.get(0),
+    )?)
+}
+
+/// Names of the tags on an item, alphabetically
+fn item_tags(conn: &rusqlite::Connection, item_id: i64) -> Result<Vec<String>> {
+    Ok(conn
+        .prepare(
+            r#"
+                SELECT b.name
+                FROM item_tag a JOIN tags b ON a.tag_id = b.id
+                WHERE a.item_id = ?
+                ORDER BY b.name
+                "#,
+        )?
+        .query_map([item_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Tags of several items in one query, keyed by item. Items without tags are left out.
+fn items_tags(conn: &rusqlite::Connection, item_ids: &[i64]) -> Result<HashMap<i64, Vec<String>>> {
+    let placeholders = vec!["?"; item_ids.len()].join(",");
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut statement = conn.prepare(&format!(
+        r#"
+            SELECT a.item_id, b.name
+            FROM item_tag a JOIN tags b ON a.tag_id = b.id
+            WHERE a.item_id IN ({placeholders})
+            ORDER BY b.name
+            "#
+    ))?;
+    let mut rows = statement.query(rusqlite::params_from_iter(item_ids))?;
+    while let Some(row) = rows.next()? {
+        tags.entry(row.get(0)?).or_default().push(row.get(1)?);
+    }
+
+    Ok(tags)
+}
+
+/// Record a change to an item and mark it modified. Must run before a deleted item's row
+/// is removed.
+fn log_history(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    action: &str,
+    field: Option<&str>,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        r#"INSERT INTO item_history(item_id, item_name, action, field, old_value, new_value)
+            SELECT id, name, ?, ?, ?, ? FROM Items WHERE id = ?"#,
+        rusqlite::params![action, field, old_value, new_value, item_id],
+    )?;
+    touch_item(conn, item_id)?;
+
+    Ok(())
+}
+
+/// Add an attribute to an item, replacing any existing value for the same key
+fn insert_attribute(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    attribute: &Attribute,
+) -> Result<()> {
+    if attribute.key.is_empty() || attribute.value.is_empty() {
+        bail!("Attribute key and value must not be empty");
+    }
+
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO item_attributes(item_id, key, value, value_type) VALUES (?,?,?,?)",
+    )?
+    .execute(rusqlite::params![
+        item_id,
+        attribute.key,
+        attribute.value,
+        attribute.value_type.as_str()
+    ])?;
+
+    Ok(())
+}
+
+/// Fail with `ContainerUnavailable` if the container doesn't exist or is in the trash
+fn ensure_container_live(conn: &rusqlite::Connection, container_id: i64) -> Result<()> {
+    let live = conn
+        .query_row(
+            "SELECT id FROM containers WHERE id = ? AND trash_id IS NULL",
+            [container_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+        .is_some();
+    if !live {
+        return Err(ContainerUnavailable(container_id).into());
+    }
+
+    Ok(())
+}
+
+/// Mark an item as modified now, for changes that aren't logged in its history
+fn touch_item(conn: &rusqlite::Connection, item_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE Items SET modified_at = CURRENT_TIMESTAMP WHERE id = ?",
+        [item_id],
+    )?;
+
+    Ok(())
+}
+
+/// History entries matching `filter`, a `WHERE`/`ORDER BY` clause taking one parameter
+fn history_entries(
+    conn: &rusqlite::Connection,
+    filter: &str,
+    param: i64,
+) -> Result<Vec<HistoryEntry>> {
+    Ok(conn
+        .prepare(&format!(
+            r#"
+                SELECT a.id, a.item_id, a.item_name, a.action, a.field, a.old_value, a.new_value, a.timestamp, b.id IS NOT NULL
+                FROM item_history a LEFT JOIN Items b ON a.item_id = b.id
+                {filter}
+                "#
+        ))?
+        .query_map([param], |row| {
+            Ok(HistoryEntry {
+                id: row.get(0)?,
+                item_id: row.get(1)?,
+                item_name: row.get(2)?,
+                action: row.get(3)?,
+                field: row.get(4)?,
+                old_value: row.get(5)?,
+                new_value: row.get(6)?,
+                timestamp: row.get(7)?,
+                exists: row.get(8)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// An item's quantity and unit as shown in its history, e.g. "3 m"
+fn quantity_text(conn: &rusqlite::Connection, item_id: i64) -> Result<String> {
+    let (quantity, unit): (i64, Option<String>) = conn.query_row(
+        "SELECT quantity, unit FROM Items WHERE id = ?",
+        [item_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    Ok(match unit {
+        Some(unit) => format!("{quantity} {unit}"),
+        None => quantity.to_string(),
+    })
+}
+
+fn log_quantity_change(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    old_quantity: &str,
+) -> Result<()> {
+    let new_quantity = quantity_text(conn, item_id)?;
+    if new_quantity != old_quantity {
+        log_history(
+            conn,
+            item_id,
+            "edit",
+            Some("quantity"),
+            Some(old_quantity),
+            Some(&new_quantity),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A container's path from ROOT as text, for records that must outlive the containers
+fn container_path_text(conn: &rusqlite::Connection, container_id: i64) -> Result<String> {
+    Ok(container_path(conn, container_id)?
+        .into_iter()
+        .map(|crumb| crumb.name)
+        .collect::<Vec<_>>()
+        .join(" › "))
+}
+
+/// An item's attributes, ordered by key
+fn item_attributes(conn: &rusqlite::Connection, item_id: i64) -> Result<Vec<Attribute>> {
+    Ok(conn
+        .prepare(
+            "SELECT key, value, value_type FROM item_attributes WHERE item_id = ? ORDER BY key",
+        )?
+        .query_map([item_id], |row| {
+            Ok(Attribute {
+                key: row.get(0)?,
+                value: row.get(1)?,
+                value_type: AttributeType::from_name(&row.get::<_, String>(2)?),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Attributes of several items in one query, keyed by item. Items without attributes are
+/// left out.
+fn items_attributes(
+    conn: &rusqlite::Connection,
+    item_ids: &[i64],
+) -> Result<HashMap<i64, Vec<Attribute>>> {
+    let placeholders = vec!["?"; item_ids.len()].join(",");
+    let mut attributes: HashMap<i64, Vec<Attribute>> = HashMap::new();
+    let mut statement = conn.prepare(&format!(
+        "SELECT item_id, key, value, value_type FROM item_attributes WHERE item_id IN ({placeholders}) ORDER BY key"
+    ))?;
+    let mut rows = statement.query(rusqlite::params_from_iter(item_ids))?;
+    while let Some(row) = rows.next()? {
+        attributes.entry(row.get(0)?).or_default().push(Attribute {
+            key: row.get(1)?,
+            value: row.get(2)?,
+            value_type: AttributeType::from_name(&row.get::<_, String>(3)?),
+        });
+    }
+
+    Ok(attributes)
+}
+
+/// The primary photo of several items in one query, keyed by item. Items without photos are
+/// left out.
+fn items_primary_photos(
+    conn: &rusqlite::Connection,
+    item_ids: &[i64],
+) -> Result<HashMap<i64, i64>> {
+    let placeholders = vec!["?"; item_ids.len()].join(",");
+    let mut primary_photos = HashMap::new();
+    let mut statement = conn.prepare(&format!(
+        "SELECT item_id, id FROM item_photos WHERE item_id IN ({placeholders}) ORDER BY position, id"
+    ))?;
+    let mut rows = statement.query(rusqlite::params_from_iter(item_ids))?;
+    while let Some(row) = rows.next()? {
+        primary_photos.entry(row.get(0)?).or_insert(row.get(1)?);
+    }
+
+    Ok(primary_photos)
+}
+
+fn delete_unused_tags(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM item_tag)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// One vector per embedded text
+type Embeddings = Vec<Vec<f32>>;
+
+/// Embed an item's name, each description statement and the full description with every
+/// index's model. Slow, so this runs before a write transaction opens.
+fn embed_item<'a>(
+    indexes: &'a VectorIndexes,
+    name: &str,
+    description_statements: &[&str],
+) -> Result<Vec<(&'a VectorIndex, Embeddings)>> {
+    indexes
+        .all()
+        .map(|index| Ok((index, embed_documents(index, name, description_statements)?)))
+        .collect()
+}
+
+fn embed_documents(
+    index: &VectorIndex,
+    name: &str,
+    description_statements: &[&str],
+) -> Result<Embeddings> {
+    let mut embedding_docs = vec![name];
+    embedding_docs.extend_from_slice(description_statements);
+
+    let full_description = description_statements.join("\n");
+    embedding_docs.push(&full_description);
+
+    index.embedder.embed_documents(&embedding_docs)
+}
+
+fn insert_embeddings(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    index: &VectorIndex,
+    embeddings: &[Vec<f32>],
+) -> Result<()> {
+    for embedding in embeddings {
+        conn.prepare(&format!(
+            "INSERT INTO {}(embedding) VALUES (?)",
+            index.vec_table
+        ))?
+        .execute(rusqlite::params![embedding.as_bytes()])?;
+        let embedding_id = conn.last_insert_rowid();
+
+        conn.execute(
+            "INSERT INTO embedding_to_item(embedding_id, item_id, vector_index) VALUES(?,?,?)",
+            [embedding_id, item_id, index.id],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn delete_embeddings(conn: &rusqlite::Connection, item_id: i64, index: &VectorIndex) -> Result<()> {
+    conn.execute(
+        &format!(
+            "DELETE FROM {} WHERE rowid IN (SELECT embedding_id FROM embedding_to_item WHERE item_id = ? AND vector_index = ?)",
+            index.vec_table
+        ),
+        [item_id, index.id],
+    )?;
+    conn.prepare("DELETE FROM embedding_to_item where item_id = ? AND vector_index = ?")?
+        .execute(rusqlite::params![item_id, index.id])?;
+
+    Ok(())
+}
+
+/// Permanently remove an item's row and everything hanging off it, except its text vectors
+fn purge_item(conn: &rusqlite::Connection, item_id: i64) -> Result<()> {
+    conn.prepare("DELETE FROM Items where id = ?")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM items_fts where rowid = ?")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM vec_item_images where rowid = ?")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM possible_duplicates where item_id = ?1 OR duplicate_of = ?1")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM item_tag where item_id = ?")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM item_attributes where item_id = ?")?
+        .execute(rusqlite::params![item_id])?;
+    conn.prepare("DELETE FROM item_photos where item_id = ?")?
+        .execute(rusqlite::params![item_id])?;
+
+    Ok(())
+}
+
+/// Append photos already in the photo store to an item's photos
+fn insert_photos(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    hashes: &[(String, String)],
+) -> Result<()> {
+    let next_position: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(position) + 1, 0) FROM item_photos WHERE item_id = ?",
+        [item_id],
+        |row| row.get(0),
+    )?;
+    for (position, (small_photo_hash, large_photo_hash)) in (next_position..).zip(hashes) {
+        conn.execute(
+            "INSERT INTO item_photos(item_id, small_photo_hash, large_photo_hash, position) VALUES (?,?,?,?)",
+            rusqlite::params![item_id, small_photo_hash, large_photo_hash, position],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Set the vector photo search matches an item by, `None` removing it
+fn replace_image_embedding(
+    conn: &rusqlite::Connection,
+    item_id: i64,
+    embedding: Option<&[f32]>,
+) -> Result<()> {
+    conn.execute("DELETE FROM vec_item_images WHERE rowid = ?", [item_id])?;
+    if let Some(embedding) = embedding {
+        conn.execute(
+            "INSERT INTO vec_item_images(rowid, embedding) VALUES (?,?)",
+            rusqlite::params![item_id, embedding.as_bytes()],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Which of these photo files no item refers to anymore. Identical photos are stored
+/// once, so a file can be shared.
+fn unused_photos(conn: &rusqlite::Connection, hashes: Vec<String>) -> Result<Vec<String>> {
+    let mut unused = Vec::new();
+    for hash in hashes {
+        let in_use: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM item_photos WHERE small_photo_hash = ?1 OR large_photo_hash = ?1)",
+            [&hash],
+            |row| row.get(0),
+        )?;
+        if !in_use {
+            unused.push(hash);
+        }
+    }
+
+    Ok(unused)
+}
+
+/// The given container plus every live container nested below it
+fn container_subtree(conn: &rusqlite::Connection, container_id: i64) -> Result<HashSet<i64>> {
+    Ok(conn
+        .prepare(
+            r#"
+                WITH RECURSIVE subtree(id) AS (
+                    SELECT ?1
+                    UNION
+                    SELECT c.id FROM containers c JOIN subtree s ON c.contained_by = s.id
+                    WHERE c.trash_id IS NULL
+                )
+                SELECT id FROM subtree
+                "#,
+        )?
+        .query_map([container_id], |row| row.get(0))?
+        .collect::<Result<HashSet<i64>, _>>()?)
+}
+
+/// Containers and items a search is limited to, `None` meaning no restriction
+struct SearchScope {
+    containers: Option<HashSet<i64>>,
+    items: Option<HashSet<i64>>,
+}
+
+impl SearchScope {
+    fn contains(&self, item_id: i64, container_id: i64) -> bool {
+        self.containers
+            .as_ref()
+            .is_none_or(|scope| scope.contains(&container_id))
+            && self
+                .items
+                .as_ref()
+                .is_none_or(|scope| scope.contains(&item_id))
+    }
+
+    /// Scoped searches filter after the knn, so widen the window to still find enough hits
+    fn window(&self) -> i64 {
+        if self.containers.is_some() || self.items.is_some() {
+            SCOPED_SEARCH_WINDOW
+        } else {
+            SEARCH_WINDOW
+        }
+    }
+}
+
+/// Load the display details of search hits, keeping their order
+fn search_results(
+    conn: &rusqlite::Connection,
+    item_ids: Vec<i64>,
+    mut similarities: HashMap<i64, f64>,
+) -> Result<Vec<ItemResult>> {
+    #[derive(Debug, Deserialize)]
+    struct QueryResult {
+        id: i64,
+        name: String,
+        description: String,
+        contained_by: i64,
+        container_name: String,
+        container_location: Option<String>,
+        quantity: i64,
+        unit: Option<String>,
+    }
+
+    let mut tags = items_tags(conn, &item_ids)?;
+    let mut attributes = items_attributes(conn, &item_ids)?;
+    let primary_photos = items_primary_photos(conn, &item_ids)?;
+
+    // hits often share containers, so only walk each path once
+    let mut paths: HashMap<i64, Vec<Breadcrumb>> = HashMap::new();
+    let mut item_results = Vec::new();
+    for item_id in item_ids {
+        let result: QueryResult = conn.query_row("SELECT a.id, a.name, a.description, a.contained_by, b.name as container_name, b.location as container_location, a.quantity, a.unit FROM Items a JOIN containers b ON a.contained_by = b.id WHERE a.id = ?", [item_id], |row| Ok(serde_rusqlite::from_row(row).unwrap()))?;
+        item_results.push(ItemResult {
+            id: result.id,
+            name: result.name,
+            description: result.description,
+            similarity: similarities.remove(&item_id).unwrap_or(0.0),
+            container_name: result.container_name,
+            container_id: result.contained_by,
+            container_location: result.container_location,
+            container_path: match paths.get(&result.contained_by) {
+                Some(path) => path.clone(),
+                None => {
+                    let path = container_path(conn, result.contained_by)?;
+                    paths.insert(result.contained_by, path.clone());
+                    path
+                }
+            },
+            relative_path: None,
+            quantity: result.quantity,
+            unit: result.unit,
+            tags: tags.remove(&item_id).unwrap_or_default(),
+            attributes: attributes.remove(&item_id).unwrap_or_default(),
+            primary_photo_id: primary_photos.get(&item_id).copied(),
+        });
+    }
+
+    Ok(item_results)
+}
+
+/// Items re-embedded per step of a re-index
+const REINDEX_BATCH_SIZE: i64 = 32;
+
+/// Number of nearest embeddings and keyword matches considered for a search
+const SEARCH_WINDOW: i64 = 100;
+
+/// Nearest embeddings checked per text when looking for duplicates of a new item
+const DUPLICATE_SEARCH_WINDOW: i64 = 20;
+
+/// Window used when results are filtered afterwards; sqlite-vec caps k at 4096
+const SCOPED_SEARCH_WINDOW: i64 = 4096;
+
+/// Bonus added to an item's similarity for each additional embedding of it that matched
+const HIT_BONUS: f64 = 0.01;
+
+/// Turn an item's best L2 distance and number of matching embeddings into a 0.0 - 1.0 score.
+/// Embeddings are normalized, so cosine similarity is 1 - d^2 / 2.
+fn similarity_score(best_distance: f64, hits: i64) -> f64 {
+    let cosine_similarity = 1.0 - best_distance * best_distance / 2.0;
+    (cosine_similarity + HIT_BONUS * (hits - 1) as f64).clamp(0.0, 1.0)
+}
+
+/// Build an FTS5 MATCH expression from free text, quoting each term so user input
+/// can't be interpreted as query syntax. Terms are prefix matched and either all required
+/// or OR'ed together.
+fn fts_query(query: &str, all_terms: bool) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{term}\"*"))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(if all_terms { " AND " } else { " OR " }))
+    }
+}
+
+/// Which of `item_ids` contain every term of `query`
+fn matching_all_terms(
+    conn: &rusqlite::Connection,
+    query: &str,
+    item_ids: &[i64],
+) -> Result<HashSet<i64>> {
+    let Some(fts_query) = fts_query(query, true) else {
+        return Ok(HashSet::new());
+    };
+    let placeholders = vec!["?"; item_ids.len()].join(",");
+    let mut params: Vec<&dyn rusqlite::ToSql> = vec![&fts_query];
+    params.extend(item_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+
+    Ok(conn
+        .prepare(&format!(
+            "SELECT rowid FROM items_fts WHERE items_fts MATCH ? AND rowid IN ({placeholders})"
+        ))?
+        .query_map(params.as_slice(), |row| row.get(0))?
+        .collect::<Result<_, _>>()?)
+}
+
+/// Merge several best-first rankings of item ids into one, scoring each id by the
+/// sum of 1 / (RRF_K + rank) over every ranking it appears in.
+fn reciprocal_rank_fusion(rankings: &[&[i64]]) -> Vec<i64> {
+    let mut scores: HashMap<i64, f64> = HashMap::new();
+    let mut order = Vec::new();
+    for ranking in rankings {
+        for (rank, item_id) in ranking.iter().enumerate() {
+            let score = scores.entry(*item_id).or_insert_with(|| {
+                order.push(*item_id);
+                0.0
+            });
+            *score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        }
+    }
+
+    // stable sort keeps the first ranking's order for ties
+    order.sort_by(|a, b| scores[b].total_cmp(&scores[a]));
+    order
+}
+
+/// Every live container, nested under ROOT
+fn container_tree(conn: &rusqlite::Connection) -> Result<ContainerTree> {
+    let mut containers: HashMap<i64, Vec<ContainerRow>> = HashMap::new();
+    let mut root = None;
+    conn.prepare("SELECT id, name, location, contained_by FROM containers WHERE trash_id IS NULL")?
+        .query_map([], |row| {
+            Ok(ContainerRow {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                location: row.get(2)?,
+                contained_by: row.get(3)?,
+            })
+        })?
+        .for_each(|row| {
+            if let Ok(row) = row {
+                if row.id == 1 {
+                    root = Some(row);
+                } else if let Some(contained_by) = row.contained_by {
+                    containers.entry(contained_by).or_default().push(row)
+                }
+            }
+        });
+
+    let Some(root) = root else {
+        bail!("Failed to find ROOT container");
+    };
+
+    let mut root = ContainerTree {
+        id: root.id,
+        name: root.name,
+        location: root.location,
+        containers: Vec::new(),
+    };
+
+    fill_tree(&mut root, &mut containers);
+
+    Ok(root)
 }
 
 #[derive(Debug)]
@@ -658,12 +2533,145 @@ fn fill_tree(cur_node: &mut ContainerTree, contained_by_map: &mut HashMap<i64, V
                 location: c.location,
                 containers: Vec::new(),
             }));
-        cur_node
-            .containers
-            .sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        cur_node.containers.sort_by_key(|c| c.name.to_lowercase());
 
         for sub_container in cur_node.containers.iter_mut() {
             fill_tree(sub_container, contained_by_map);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn containers(rows: &[(i64, &str, Option<i64>)]) -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE containers(id INTEGER PRIMARY KEY, name TEXT NOT NULL, location TEXT, contained_by INTEGER, trash_id INTEGER)",
+            [],
+        )
+        .unwrap();
+        for (id, name, contained_by) in rows {
+            conn.execute(
+                "INSERT INTO containers(id, name, contained_by) VALUES (?,?,?)",
+                rusqlite::params![id, name, contained_by],
+            )
+            .unwrap();
+        }
+
+        conn
+    }
+
+    fn names(path: Vec<Breadcrumb>) -> Vec<String> {
+        path.into_iter().map(|crumb| crumb.name).collect()
+    }
+
+    #[test]
+    fn container_path_from_root() {
+        let conn = containers(&[
+            (1, "ROOT", None),
+            (2, "Garage", Some(1)),
+            (3, "Shelf", Some(2)),
+        ]);
+
+        assert_eq!(
+            names(container_path(&conn, 3).unwrap()),
+            ["ROOT", "Garage", "Shelf"]
+        );
+    }
+
+    #[test]
+    fn container_path_stops_in_cycle() {
+        let conn = containers(&[
+            (1, "ROOT", None),
+            (2, "A", Some(3)),
+            (3, "B", Some(4)),
+            (4, "C", Some(2)),
+        ]);
+
+        assert_eq!(names(container_path(&conn, 2).unwrap()), ["C", "B", "A"]);
+    }
+
+    #[test]
+    fn fts_query_quotes_terms_as_prefixes() {
+        assert_eq!(
+            fts_query("red screwdriver", false).as_deref(),
+            Some(r#""red"* OR "screwdriver"*"#)
+        );
+        assert_eq!(
+            fts_query("M3-0.5", true).as_deref(),
+            Some(r#""M3"* AND "0"* AND "5"*"#)
+        );
+    }
+
+    #[test]
+    fn fts_query_drops_fts_syntax() {
+        assert_eq!(
+            fts_query(r#"AC/DC "box" NEAR(a*b) -x:y"#, false).as_deref(),
+            Some(r#""AC"* OR "DC"* OR "box"* OR "NEAR"* OR "a"* OR "b"* OR "x"* OR "y"*"#)
+        );
+        assert_eq!(fts_query(r#" "*():- "#, false), None);
+        assert_eq!(fts_query("", true), None);
+    }
+
+    #[test]
+    fn only_hits_on_every_term_match_all_terms() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE VIRTUAL TABLE items_fts USING fts5(name, description)",
+            [],
+        )
+        .unwrap();
+        for (id, name, description) in [
+            (1, "Cordless drill", "A drill with two batteries"),
+            (2, "Drill bits", "HSS, assorted"),
+            (3, "Tape", "A roll of duct tape"),
+            (4, "Socket head screw", "M3-0.5 x 10"),
+            (5, "Socket head screw", "M4-0.7 x 10"),
+        ] {
+            conn.execute(
+                "INSERT INTO items_fts(rowid, name, description) VALUES (?,?,?)",
+                rusqlite::params![id, name, description],
+            )
+            .unwrap();
+        }
+        let all = [1, 2, 3, 4, 5];
+
+        assert_eq!(
+            matching_all_terms(&conn, "a drill", &all).unwrap(),
+            HashSet::from([1, 2])
+        );
+        assert_eq!(
+            matching_all_terms(&conn, "M3-0.5", &all).unwrap(),
+            HashSet::from([4])
+        );
+        assert_eq!(
+            matching_all_terms(&conn, "a drill", &[2, 3]).unwrap(),
+            HashSet::from([2])
+        );
+        assert!(matching_all_terms(&conn, "--", &all).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rrf_ranks_ids_in_both_rankings_first() {
+        assert_eq!(reciprocal_rank_fusion(&[&[1, 2, 3], &[3, 4]]), [3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn rrf_keeps_first_ranking_order_for_ties() {
+        assert_eq!(reciprocal_rank_fusion(&[&[1, 2], &[3, 4]]), [1, 3, 2, 4]);
+        assert_eq!(reciprocal_rank_fusion(&[&[], &[5, 6]]), [5, 6]);
+        assert!(reciprocal_rank_fusion(&[]).is_empty());
+    }
+
+    #[test]
+    fn similarity_score_from_distance_and_hits() {
+        assert_eq!(similarity_score(0.0, 1), 1.0);
+        assert!((similarity_score(1.0, 1) - 0.5).abs() < 1e-9);
+        assert!((similarity_score(1.0, 3) - 0.52).abs() < 1e-9);
+        // opposite vectors and big bonuses stay within 0.0 - 1.0
+        assert_eq!(similarity_score(2.0, 1), 0.0);
+        assert_eq!(similarity_score(0.1, 50), 1.0);
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+/// A structured fact about an item, such as `voltage = 12V`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    pub key: String,
+    pub value: String,
+    pub value_type: AttributeType,
+}
+
+/// How an attribute's value is compared when filtering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    /// Compared case-insensitively
+    Text,
+    /// A number with an optional unit, e.g. `12V` or `0.5 mm`
+    Number,
+    /// yes/no, true/false or 1/0
+    Boolean,
+}
+
+impl AttributeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::Text => "text",
+            AttributeType::Number => "number",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+
+    /// Unknown names are treated as text
+    pub fn from_name(name: &str) -> AttributeType {
+        match name.trim().to_lowercase().as_str() {
+            "number" => AttributeType::Number,
+            "boolean" => AttributeType::Boolean,
+            _ => AttributeType::Text,
+        }
+    }
+}
+
+impl Attribute {
+    /// Keys are trimmed and lowercased. A value that doesn't fit its type is kept as text.
+    pub fn new(key: &str, value: &str, value_type: AttributeType) -> Attribute {
+        let value = value.trim();
+        let value_type = match value_type {
+            AttributeType::Number if split_number(value).is_none() => AttributeType::Text,
+            AttributeType::Boolean if parse_boolean(value).is_none() => AttributeType::Text,
+            value_type => value_type,
+        };
+
+        Attribute {
+            key: key.trim().to_lowercase(),
+            value: value.to_string(),
+            value_type,
+        }
+    }
+
+    /// Whether this attribute's value equals a filter value given by the user
+    pub fn matches(&self, value: &str) -> bool {
+        let value = value.trim();
+        match self.value_type {
+            AttributeType::Text => self.value.eq_ignore_ascii_case(value),
+            AttributeType::Number => match (split_number(&self.value), split_number(value)) {
+                (Some((a, a_unit)), Some((b, b_unit))) => {
+                    a == b && (b_unit.is_empty() || a_unit.eq_ignore_ascii_case(&b_unit))
+                }
+                _ => false,
+            },
+            AttributeType::Boolean => {
+                parse_boolean(value).is_some_and(|b| parse_boolean(&self.value) == Some(b))
+            }
+        }
+    }
+}
+
+/// Parse a `key=value` search filter
+pub fn parse_filter(filter: &str) -> Option<(String, String)> {
+    let (key, value) = filter.split_once('=')?;
+    let key = key.trim().to_lowercase();
+    let value = value.trim().to_string();
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+
+    Some((key, value))
+}
+
+/// Split `12.5 V` into `(12.5, "V")`
+fn split_number(value: &str) -> Option<(f64, String)> {
+    let end = value
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || *c == '.' || (*i == 0 && *c == '-')))
+        .map(|(i, _)| i)
+        .unwrap_or(value.len());
+    let number = value[..end].parse().ok()?;
+
+    Some((number, value[end..].trim().to_string()))
+}
+
+fn parse_boolean(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(value: &str) -> Attribute {
+        Attribute::new("size", value, AttributeType::Number)
+    }
+
+    #[test]
+    fn splits_numbers_from_units() {
+        assert_eq!(split_number("12V"), Some((12.0, String::from("V"))));
+        assert_eq!(split_number("0.5 mm"), Some((0.5, String::from("mm"))));
+        assert_eq!(split_number("-3.5"), Some((-3.5, String::new())));
+        assert_eq!(split_number("V12"), None);
+        assert_eq!(split_number("-"), None);
+        assert_eq!(split_number("1-2"), Some((1.0, String::from("-2"))));
+    }
+
+    #[test]
+    fn numbers_match_by_value_and_unit() {
+        assert!(number("12V").matches("12 v"));
+        assert!(number("12 V").matches("12.0V"));
+        assert!(!number("12V").matches("12mm"));
+        assert!(!number("12V").matches("13V"));
+        assert!(number("-3.5").matches("-3.5"));
+        assert!(number("0.5 mm").matches(" .5mm "));
+    }
+
+    #[test]
+    fn number_filter_without_unit_matches_any_unit() {
+        assert!(number("12V").matches("12"));
+        assert!(number("12").matches("12"));
+        assert!(!number("12").matches("12V"));
+        assert!(!number("12V").matches("twelve"));
+    }
+
+    #[test]
+    fn booleans_match_any_spelling() {
+        let attribute = Attribute::new("cordless", "Yes", AttributeType::Boolean);
+        assert!(attribute.matches("true"));
+        assert!(attribute.matches("1"));
+        assert!(!attribute.matches("no"));
+        assert!(!attribute.matches("maybe"));
+        assert!(Attribute::new("cordless", "0", AttributeType::Boolean).matches("False"));
+    }
+
+    #[test]
+    fn text_matches_ignoring_case() {
+        let attribute = Attribute::new("color", "Red", AttributeType::Text);
+        assert!(attribute.matches(" red "));
+        assert!(!attribute.matches("reddish"));
+    }
+
+    #[test]
+    fn values_that_dont_fit_their_type_become_text() {
+        let attribute = Attribute::new(" Voltage ", " twelve ", AttributeType::Number);
+        assert_eq!(attribute.key, "voltage");
+        assert_eq!(attribute.value, "twelve");
+        assert_eq!(attribute.value_type, AttributeType::Text);
+        assert_eq!(
+            Attribute::new("cordless", "sometimes", AttributeType::Boolean).value_type,
+            AttributeType::Text
+        );
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!(
+            parse_filter(" Voltage = 12V "),
+            Some((String::from("voltage"), String::from("12V")))
+        );
+        assert_eq!(
+            parse_filter("a=b=c"),
+            Some((String::from("a"), String::from("b=c")))
+        );
+        assert_eq!(parse_filter("=12V"), None);
+        assert_eq!(parse_filter("voltage="), None);
+        assert_eq!(parse_filter("voltage"), None);
+    }
+}
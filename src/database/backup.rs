@@ -0,0 +1,455 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Seek, Write},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+
+use super::{
+    container_tree, embed_item, insert_embeddings, insert_photos, item_attributes, item_tags,
+    photo_store::PhotoStore, replace_image_embedding, unused_photos, Attribute, ContainerTree,
+    Database,
+};
+
+/// Bumped whenever the manifest layout changes incompatibly. Fields added later, like
+/// the item timestamps, are optional so older archives still restore.
+const MANIFEST_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Everything needed to rebuild the inventory. Vectors are left out, restoring re-embeds
+/// with whatever models are configured then.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    exported_at: String,
+    containers: ContainerTree,
+    items: Vec<BackupItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupItem {
+    id: i64,
+    name: String,
+    description: String,
+    container_id: i64,
+    quantity: i64,
+    unit: Option<String>,
+    /// Missing from archives written before items had timestamps
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    modified_at: Option<String>,
+    tags: Vec<String>,
+    attributes: Vec<Attribute>,
+    /// Primary first, as hashes of files under `photos/` in the archive
+    photos: Vec<BackupPhoto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupPhoto {
+    small: String,
+    large: String,
+}
+
+/// What an export or restore covered
+#[derive(Debug, Serialize)]
+pub struct BackupSummary {
+    pub containers: usize,
+    pub items: usize,
+    pub photos: usize,
+}
+
+impl Database {
+    /// Write every live container and item with their photos to a zip archive. Reads
+    /// happen in one transaction, so the archive is a consistent snapshot.
+    #[tracing::instrument(skip(writer))]
+    pub fn export_backup(&self, writer: impl Write + Seek) -> Result<BackupSummary> {
+        let conn = self.pool.read();
+        let tx = conn.unchecked_transaction()?;
+
+        let containers = container_tree(&tx)?;
+        let exported_at: String = tx.query_row("SELECT datetime('now')", [], |row| row.get(0))?;
+
+        let mut items: Vec<BackupItem> = tx
+            .prepare(
+                r#"SELECT id, name, description, contained_by, quantity, unit, created_at, modified_at
+                    FROM Items WHERE trash_id IS NULL ORDER BY id"#,
+            )?
+            .query_map([], |row| {
+                Ok(BackupItem {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    description: row.get(2)?,
+                    container_id: row.get(3)?,
+                    quantity: row.get(4)?,
+                    unit: row.get(5)?,
+                    created_at: row.get(6)?,
+                    modified_at: row.get(7)?,
+                    tags: Vec::new(),
+                    attributes: Vec::new(),
+                    photos: Vec::new(),
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut container_ids = HashSet::new();
+        collect_container_ids(&containers, &mut container_ids);
+
+        for item in items.iter_mut() {
+            // a restore needs every item to have a container to go in
+            if !container_ids.contains(&item.container_id) {
+                warn!(
+                    "Item {} is in missing container {}, exporting it in ROOT",
+                    item.id, item.container_id
+                );
+                item.container_id = 1;
+            }
+            item.tags = item_tags(&tx, item.id)?;
+            item.attributes = item_attributes(&tx, item.id)?;
+            item.photos = tx
+                .prepare(
+                    r#"SELECT small_photo_hash, large_photo_hash FROM item_photos
+                        WHERE item_id = ? ORDER BY position, id"#,
+                )?
+                .query_map([item.id], |row| {
+                    Ok(BackupPhoto {
+                        small: row.get(0)?,
+                        large: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+        }
+        tx.finish()?;
+        drop(conn);
+
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            exported_at,
+            containers,
+            items,
+        };
+        let photos = write_archive(writer, &manifest, &self.photos)?;
+
+        let summary = BackupSummary {
+            containers: count_containers(&manifest.containers) - 1,
+            items: manifest.items.len(),
+            photos,
+        };
+        info!("Exported {:?}", summary);
+
+        Ok(summary)
+    }
+
+    /// Replace the whole inventory with the contents of a backup archive, re-embedding
+    /// every item. Trash, history and possible duplicates are cleared.
+    #[tracing::instrument(skip(reader))]
+    pub fn restore_backup(&self, reader: impl Read + Seek) -> Result<BackupSummary> {
+        // photos go into the store first, it only ever adds files
+        let (manifest, restored_photos) = read_archive(reader, &self.photos)?;
+
+        let indexes = self.indexes.read().unwrap();
+        let mut embedded = Vec::new();
+        for (i, item) in manifest.items.iter().enumerate() {
+            info!("Embedding item {} of {}", i + 1, manifest.items.len());
+            let description_statements: Vec<&str> = item.description.split("\n").collect();
+            let embeddings = embed_item(&indexes, &item.name, &description_statements)?;
+            let image_embedding = match item.photos.first() {
+                Some(primary) => match self.photos.get(&primary.large) {
+                    Ok(photo) => Some(self.embed_photo(&photo)?),
+                    Err(e) => {
+                        warn!("Not embedding the photo of item {}: {}", item.id, e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            embedded.push((embeddings, image_embedding));
+        }
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let old_photos = tx
+            .prepare("SELECT small_photo_hash FROM item_photos UNION SELECT large_photo_hash FROM item_photos")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for index in indexes.all() {
+            tx.execute(&format!("DELETE FROM {}", index.vec_table), [])?;
+        }
+        for table in [
+            "embedding_to_item",
+            "vec_item_images",
+            "items_fts",
+            "item_photos",
+            "item_tag",
+            "tags",
+            "item_attributes",
+            "item_history",
+            "possible_duplicates",
+            "trash",
+            "Items",
+        ] {
+            tx.execute(&format!("DELETE FROM {table}"), [])?;
+        }
+        tx.execute("DELETE FROM containers WHERE id != 1", [])?;
+
+        tx.execute(
+            "UPDATE containers SET name = ?, location = ?, trash_id = NULL WHERE id = 1",
+            rusqlite::params![manifest.containers.name, manifest.containers.location],
+        )?;
+        insert_containers(&tx, &manifest.containers)?;
+
+        for (item, (embeddings, image_embedding)) in manifest.items.iter().zip(&embedded) {
+            tx.execute(
+                r#"INSERT INTO Items(id, name, description, contained_by, quantity, unit, created_at, modified_at)
+                    VALUES (?,?,?,?,?,?,COALESCE(?, CURRENT_TIMESTAMP),COALESCE(?, CURRENT_TIMESTAMP))"#,
+                rusqlite::params![
+                    item.id,
+                    item.name,
+                    item.description,
+                    item.container_id,
+                    item.quantity,
+                    item.unit,
+                    item.created_at,
+                    item.modified_at
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO items_fts(rowid, name, description) VALUES (?,?,?)",
+                rusqlite::params![item.id, item.name, item.description],
+            )?;
+            for (index, embeddings) in embeddings {
+                insert_embeddings(&tx, item.id, index, embeddings)?;
+            }
+
+            let photo_hashes: Vec<(String, String)> = item
+                .photos
+                .iter()
+                .map(|photo| (photo.small.clone(), photo.large.clone()))
+                .collect();
+            insert_photos(&tx, item.id, &photo_hashes)?;
+            replace_image_embedding(&tx, item.id, image_embedding.as_deref())?;
+
+            for tag in &item.tags {
+                tx.execute("INSERT OR IGNORE INTO tags(name) VALUES (?)", [tag])?;
+                tx.execute(
+                    "INSERT OR IGNORE INTO item_tag(item_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+                    rusqlite::params![item.id, tag],
+                )?;
+            }
+            for attribute in &item.attributes {
+                tx.execute(
+                    "INSERT OR REPLACE INTO item_attributes(item_id, key, value, value_type) VALUES (?,?,?,?)",
+                    rusqlite::params![
+                        item.id,
+                        attribute.key,
+                        attribute.value,
+                        attribute.value_type.as_str()
+                    ],
+                )?;
+            }
+        }
+
+        let unused_photos = unused_photos(&tx, old_photos)?;
+        tx.commit()?;
+        drop(conn);
+
+        self.remove_photo_files(unused_photos);
+
+        let summary = BackupSummary {
+            containers: count_containers(&manifest.containers) - 1,
+            items: manifest.items.len(),
+            photos: restored_photos.len(),
+        };
+        info!("Restored {:?}", summary);
+
+        Ok(summary)
+    }
+}
+
+/// Write the manifest and every photo it references to a zip archive, returning how many
+/// photos were written. Photos missing from the store are left out of the archive.
+fn write_archive(
+    writer: impl Write + Seek,
+    manifest: &Manifest,
+    photos: &PhotoStore,
+) -> Result<usize> {
+    let mut zip = zip::ZipWriter::new(writer);
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+    serde_json::to_writer_pretty(&mut zip, manifest)?;
+
+    // JPEGs don't compress any further
+    let photo_options =
+        SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut seen = HashSet::new();
+    let mut written = 0;
+    for hash in manifest
+        .items
+        .iter()
+        .flat_map(|item| &item.photos)
+        .flat_map(|photo| [&photo.small, &photo.large])
+    {
+        if !seen.insert(hash) {
+            continue;
+        }
+        // purged while exporting, the restore drops photos it can't find
+        let Ok(photo) = photos.get(hash) else {
+            warn!("Photo {hash} disappeared during export");
+            continue;
+        };
+        zip.start_file(format!("photos/{hash}.jpg"), photo_options)?;
+        zip.write_all(&photo)?;
+        written += 1;
+    }
+    zip.finish()?;
+
+    Ok(written)
+}
+
+/// Read the manifest of an archive and put its photos in `photos`. Items lose the photos
+/// the archive doesn't contain. Returns the manifest and the hashes of the restored photos.
+fn read_archive(
+    reader: impl Read + Seek,
+    photos: &PhotoStore,
+) -> Result<(Manifest, HashSet<String>)> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+    let mut manifest: Manifest = serde_json::from_reader(
+        zip.by_name(MANIFEST_NAME)
+            .context("Archive has no manifest")?,
+    )?;
+    if manifest.version != MANIFEST_VERSION {
+        bail!("Unsupported backup version {}", manifest.version);
+    }
+    if manifest.containers.id != 1 {
+        bail!("Backup doesn't start at the ROOT container");
+    }
+
+    let mut restored = HashSet::new();
+    let mut missing = HashSet::new();
+    for item in manifest.items.iter_mut() {
+        for photo in &item.photos {
+            for hash in [&photo.small, &photo.large] {
+                if restored.contains(hash) || missing.contains(hash) {
+                    continue;
+                }
+                let Ok(mut file) = zip.by_name(&format!("photos/{hash}.jpg")) else {
+                    warn!("Archive is missing photo {hash} of item {}", item.id);
+                    missing.insert(hash.clone());
+                    continue;
+                };
+                let mut photo = Vec::new();
+                file.read_to_end(&mut photo)?;
+                if photos.put(&photo)? != *hash {
+                    bail!("Photo {hash} in the archive is corrupt");
+                }
+                restored.insert(hash.clone());
+            }
+        }
+        item.photos
+            .retain(|photo| restored.contains(&photo.small) && restored.contains(&photo.large));
+    }
+
+    Ok((manifest, restored))
+}
+
+/// Insert the children of `parent` and everything below them, keeping their ids
+fn insert_containers(conn: &rusqlite::Connection, parent: &ContainerTree) -> Result<()> {
+    for container in &parent.containers {
+        conn.execute(
+            "INSERT INTO containers(id, name, location, contained_by) VALUES (?,?,?,?)",
+            rusqlite::params![container.id, container.name, container.location, parent.id],
+        )?;
+        insert_containers(conn, container)?;
+    }
+
+    Ok(())
+}
+
+fn collect_container_ids(tree: &ContainerTree, ids: &mut HashSet<i64>) {
+    ids.insert(tree.id);
+    for container in &tree.containers {
+        collect_container_ids(container, ids);
+    }
+}
+
+fn count_containers(tree: &ContainerTree) -> usize {
+    1 + tree.containers.iter().map(count_containers).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: i64, photos: Vec<BackupPhoto>) -> BackupItem {
+        BackupItem {
+            id,
+            name: format!("Item {id}"),
+            description: String::new(),
+            container_id: 1,
+            quantity: 1,
+            unit: None,
+            created_at: None,
+            modified_at: None,
+            tags: Vec::new(),
+            attributes: Vec::new(),
+            photos,
+        }
+    }
+
+    #[test]
+    fn round_trip_drops_missing_photos() {
+        let dir = tempfile::tempdir().unwrap();
+        let exported = PhotoStore::open(dir.path().join("exported")).unwrap();
+        let restored = PhotoStore::open(dir.path().join("restored")).unwrap();
+        let kept = BackupPhoto {
+            small: exported.put(b"small").unwrap(),
+            large: exported.put(b"large").unwrap(),
+        };
+        let expected = (kept.small.clone(), kept.large.clone());
+        let gone = BackupPhoto {
+            small: exported.put(b"small").unwrap(),
+            large: "0".repeat(64),
+        };
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            exported_at: String::from("2024-01-01 00:00:00"),
+            containers: ContainerTree {
+                id: 1,
+                name: String::from("ROOT"),
+                location: None,
+                containers: Vec::new(),
+            },
+            items: vec![
+                item(1, vec![gone, kept]),
+                item(
+                    2,
+                    vec![BackupPhoto {
+                        small: "1".repeat(64),
+                        large: "1".repeat(64),
+                    }],
+                ),
+            ],
+        };
+
+        let mut archive = std::io::Cursor::new(Vec::new());
+        assert_eq!(
+            write_archive(&mut archive, &manifest, &exported).unwrap(),
+            2
+        );
+        let (manifest, restored_photos) = read_archive(archive, &restored).unwrap();
+
+        assert_eq!(restored_photos.len(), 2);
+        let photos: Vec<(String, String)> = manifest.items[0]
+            .photos
+            .iter()
+            .map(|photo| (photo.small.clone(), photo.large.clone()))
+            .collect();
+        assert_eq!(photos, std::slice::from_ref(&expected));
+        assert!(manifest.items[1].photos.is_empty());
+        assert_eq!(restored.get(&expected.1).unwrap(), b"large");
+    }
+}
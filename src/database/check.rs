@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::{delete_embeddings, embed_item, insert_embeddings, Database};
+
+/// Problems found by `Database::check`
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    /// Vectors, mappings and photo vectors that belong to no item
+    pub orphaned_embeddings: usize,
+    /// Items with no text vectors in the active index
+    pub items_missing_embeddings: Vec<i64>,
+    /// Items and containers whose parent container no longer exists or is in the trash
+    /// while they are not
+    pub items_in_missing_containers: Vec<i64>,
+    pub containers_in_missing_containers: Vec<i64>,
+    /// Containers that are their own ancestor, one per cycle
+    pub container_cycles: Vec<i64>,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_embeddings == 0
+            && self.items_missing_embeddings.is_empty()
+            && self.items_in_missing_containers.is_empty()
+            && self.containers_in_missing_containers.is_empty()
+            && self.container_cycles.is_empty()
+    }
+}
+
+/// A fix for one kind of problem in a `CheckReport`
+#[derive(Debug, Clone, Copy)]
+pub enum Repair {
+    /// Delete vectors that belong to no item
+    OrphanedEmbeddings,
+    /// Re-embed items without vectors
+    MissingEmbeddings,
+    /// Move items and containers with a missing parent to ROOT
+    MissingContainers,
+    /// Break each cycle by moving one of its containers to ROOT
+    ContainerCycles,
+}
+
+impl Repair {
+    pub const ALL: [Repair; 4] = [
+        Repair::OrphanedEmbeddings,
+        Repair::MissingEmbeddings,
+        Repair::MissingContainers,
+        Repair::ContainerCycles,
+    ];
+
+    pub fn from_name(name: &str) -> Result<Repair> {
+        Ok(match name {
+            "orphaned_embeddings" => Repair::OrphanedEmbeddings,
+            "missing_embeddings" => Repair::MissingEmbeddings,
+            "missing_containers" => Repair::MissingContainers,
+            "container_cycles" => Repair::ContainerCycles,
+            _ => bail!("Unknown repair: {name}"),
+        })
+    }
+}
+
+impl Database {
+    /// Look for inconsistencies left behind by interrupted writes. Changes nothing.
+    #[tracing::instrument]
+    pub fn check(&self) -> Result<CheckReport> {
+        let indexes = self.indexes.read().unwrap();
+        let conn = self.pool.read();
+        let mut report = CheckReport::default();
+
+        for index in indexes.all() {
+            report.orphaned_embeddings += conn.query_row(
+                &format!(
+                    r#"SELECT COUNT(*) FROM {} WHERE rowid NOT IN
+                        (SELECT embedding_id FROM embedding_to_item WHERE vector_index = ?)"#,
+                    index.vec_table
+                ),
+                [index.id],
+                |row| row.get::<_, usize>(0),
+            )?;
+        }
+        report.orphaned_embeddings += conn.query_row(
+            "SELECT COUNT(*) FROM embedding_to_item WHERE item_id NOT IN (SELECT id FROM Items)",
+            [],
+            |row| row.get::<_, usize>(0),
+        )?;
+        report.orphaned_embeddings += conn.query_row(
+            "SELECT COUNT(*) FROM vec_item_images WHERE rowid NOT IN (SELECT id FROM Items)",
+            [],
+            |row| row.get::<_, usize>(0),
+        )?;
+
+        report.items_missing_embeddings = conn
+            .prepare(
+                r#"SELECT id FROM Items WHERE id NOT IN
+                    (SELECT item_id FROM embedding_to_item WHERE vector_index = ?)"#,
+            )?
+            .query_map([indexes.active.id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        report.items_in_missing_containers = conn
+            .prepare(
+                r#"SELECT id FROM Items
+                    WHERE contained_by NOT IN (SELECT id FROM containers)
+                    OR (trash_id IS NULL AND contained_by IN (SELECT id FROM containers WHERE trash_id IS NOT NULL))"#,
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        report.containers_in_missing_containers = conn
+            .prepare(
+                r#"SELECT id FROM containers
+                    WHERE id != 1 AND (
+                        contained_by IS NULL
+                        OR contained_by NOT IN (SELECT id FROM containers)
+                        OR (trash_id IS NULL AND contained_by IN (SELECT id FROM containers WHERE trash_id IS NOT NULL))
+                    )"#,
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        report.container_cycles = container_cycles(&conn)?;
+
+        Ok(report)
+    }
+
+    /// Apply one kind of repair, returning how many items or containers it touched
+    #[tracing::instrument]
+    pub fn repair(&self, repair: Repair) -> Result<usize> {
+        let report = self.check()?;
+        let fixed = match repair {
+            Repair::OrphanedEmbeddings => {
+                let indexes = self.indexes.read().unwrap();
+                let mut conn = self.pool.write();
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "DELETE FROM embedding_to_item WHERE item_id NOT IN (SELECT id FROM Items)",
+                    [],
+                )?;
+                for index in indexes.all() {
+                    tx.execute(
+                        &format!(
+                            r#"DELETE FROM {} WHERE rowid NOT IN
+                                (SELECT embedding_id FROM embedding_to_item WHERE vector_index = ?)"#,
+                            index.vec_table
+                        ),
+                        [index.id],
+                    )?;
+                }
+                tx.execute(
+                    "DELETE FROM vec_item_images WHERE rowid NOT IN (SELECT id FROM Items)",
+                    [],
+                )?;
+                tx.commit()?;
+
+                report.orphaned_embeddings
+            }
+            Repair::MissingEmbeddings => {
+                for item_id in &report.items_missing_embeddings {
+                    self.reembed_item(*item_id)?;
+                }
+
+                report.items_missing_embeddings.len()
+            }
+            Repair::MissingContainers => {
+                let mut conn = self.pool.write();
+                let tx = conn.transaction()?;
+                for item_id in &report.items_in_missing_containers {
+                    tx.execute("UPDATE Items SET contained_by = 1 WHERE id = ?", [item_id])?;
+                }
+                for container_id in &report.containers_in_missing_containers {
+                    tx.execute(
+                        "UPDATE containers SET contained_by = 1 WHERE id = ?",
+                        [container_id],
+                    )?;
+                }
+                tx.commit()?;
+
+                report.items_in_missing_containers.len()
+                    + report.containers_in_missing_containers.len()
+            }
+            Repair::ContainerCycles => {
+                let mut conn = self.pool.write();
+                let tx = conn.transaction()?;
+                for container_id in &report.container_cycles {
+                    tx.execute(
+                        "UPDATE containers SET contained_by = 1 WHERE id = ?",
+                        [container_id],
+                    )?;
+                }
+                tx.commit()?;
+
+                report.container_cycles.len()
+            }
+        };
+
+        info!("{:?} repaired {} entries", repair, fixed);
+
+        Ok(fixed)
+    }
+
+    /// Run every check and apply every repair, logging what was found
+    #[tracing::instrument]
+    pub fn check_and_repair(&self) -> Result<CheckReport> {
+        let report = self.check()?;
+        if report.is_clean() {
+            info!("Database check found no problems");
+            return Ok(report);
+        }
+
+        warn!("Database check found problems: {:?}", report);
+        for repair in Repair::ALL {
+            self.repair(repair)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Replace an item's text vectors in every index
+    fn reembed_item(&self, item_id: i64) -> Result<()> {
+        let indexes = self.indexes.read().unwrap();
+        let (name, description): (String, String) = self.pool.read().query_row(
+            "SELECT name, description FROM Items WHERE id = ?",
+            [item_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let description_statements: Vec<&str> = description.split("\n").collect();
+        let embeddings = embed_item(&indexes, &name, &description_statements)?;
+
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        for (index, embeddings) in &embeddings {
+            delete_embeddings(&tx, item_id, index)?;
+            insert_embeddings(&tx, item_id, index, embeddings)?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+/// Walk up from every container. A walk that comes back to a container already on its
+/// own path is a cycle; the container it came back to is reported once per cycle.
+fn container_cycles(conn: &rusqlite::Connection) -> Result<Vec<i64>> {
+    let parents: std::collections::HashMap<i64, Option<i64>> = conn
+        .prepare("SELECT id, contained_by FROM containers")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut settled: HashSet<i64> = HashSet::new();
+    let mut cycles = Vec::new();
+    for start in parents.keys() {
+        let mut path = Vec::new();
+        let mut on_path = HashSet::new();
+        let mut current = Some(*start);
+        while let Some(container_id) = current {
+            if settled.contains(&container_id) {
+                break;
+            }
+            if !on_path.insert(container_id) {
+                cycles.push(container_id);
+                break;
+            }
+            path.push(container_id);
+            current = parents.get(&container_id).copied().flatten();
+        }
+        settled.extend(path);
+    }
+
+    Ok(cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn containers(rows: &[(i64, Option<i64>)]) -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE containers(id INTEGER PRIMARY KEY, contained_by INTEGER)",
+            [],
+        )
+        .unwrap();
+        for (id, contained_by) in rows {
+            conn.execute(
+                "INSERT INTO containers(id, contained_by) VALUES (?,?)",
+                rusqlite::params![id, contained_by],
+            )
+            .unwrap();
+        }
+
+        conn
+    }
+
+    #[test]
+    fn tree_has_no_cycles() {
+        let conn = containers(&[(1, None), (2, Some(1)), (3, Some(2)), (4, Some(1))]);
+
+        assert!(container_cycles(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_each_cycle_once() {
+        // 2 and 3 contain each other, 4 contains itself, 5 hangs off the first cycle
+        let conn = containers(&[
+            (1, None),
+            (2, Some(3)),
+            (3, Some(2)),
+            (4, Some(4)),
+            (5, Some(2)),
+            (6, Some(1)),
+        ]);
+
+        let mut cycles = container_cycles(&conn).unwrap();
+        cycles.sort();
+
+        assert_eq!(cycles.len(), 2);
+        assert!([2, 3].contains(&cycles[0]));
+        assert_eq!(cycles[1], 4);
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use anyhow::{bail, Context, Result};
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use tracing::info;
+
+use super::{
+    container_path, container_path_text, delete_embeddings, embed_item, insert_embeddings,
+    log_history, log_quantity_change, quantity_text, Database,
+};
+
+const HEADER: [&str; 6] = ["id", "name", "description", "container", "quantity", "unit"];
+
+/// Separates container names in the `container` column, e.g. `Garage / Shelf 2`. ROOT is
+/// left out. A `/` without spaces around it is part of a name.
+const PATH_SEPARATOR: &str = " / ";
+
+/// The `container` cell of items directly in ROOT, as an empty cell means unchanged
+const ROOT_PATH: &str = "/";
+
+/// One item row of an imported spreadsheet. Missing columns or empty cells leave the
+/// item's current value alone.
+#[derive(Debug)]
+struct CsvItem {
+    id: Option<i64>,
+    name: String,
+    description: Option<String>,
+    container: Option<Vec<String>>,
+    quantity: Option<i64>,
+    unit: Option<String>,
+}
+
+/// What a CSV import changed
+#[derive(Debug, Default, Serialize)]
+pub struct CsvImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub containers_created: usize,
+}
+
+impl Database {
+    /// Every live item as CSV with the columns in `HEADER`
+    #[tracing::instrument]
+    pub fn export_csv(&self) -> Result<String> {
+        let conn = self.pool.read();
+        let rows: Vec<(i64, String, String, i64, i64, Option<String>)> = conn
+            .prepare(
+                r#"SELECT id, name, description, contained_by, quantity, unit FROM Items
+                    WHERE trash_id IS NULL ORDER BY id"#,
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut paths: HashMap<i64, String> = HashMap::new();
+        let mut csv = String::new();
+        write_record(&mut csv, &HEADER);
+        for (id, name, description, container_id, quantity, unit) in rows {
+            let path = match paths.entry(container_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(
+                    container_path(&conn, container_id)?
+                        .into_iter()
+                        .skip(1)
+                        .map(|crumb| crumb.name)
+                        .collect::<Vec<_>>()
+                        .join(PATH_SEPARATOR),
+                ),
+            };
+            if path.is_empty() {
+                path.push_str(ROOT_PATH);
+            }
+
+            write_record(
+                &mut csv,
+                &[
+                    &id.to_string(),
+                    &name,
+                    &description,
+                    path,
+                    &quantity.to_string(),
+                    unit.as_deref().unwrap_or_default(),
+                ],
+            );
+        }
+
+        Ok(csv)
+    }
+
+    /// Create or update items from CSV. Rows with the id of a live item update it, others
+    /// become new items, and containers in the path that don't exist yet are created.
+    /// Nothing is written if any row is invalid.
+    #[tracing::instrument(skip(csv))]
+    pub fn import_csv(&self, csv: &str) -> Result<CsvImportSummary> {
+        let rows = parse_items(csv)?;
+
+        // existing text, to only re-embed rows whose name or description changed
+        let mut existing: HashMap<i64, (String, String)> = HashMap::new();
+        {
+            let conn = self.pool.read();
+            for id in rows.iter().filter_map(|row| row.id) {
+                let item = conn
+                    .query_row(
+                        "SELECT name, description FROM Items WHERE id = ? AND trash_id IS NULL",
+                        [id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?;
+                if let Some(item) = item {
+                    existing.insert(id, item);
+                }
+            }
+        }
+
+        let indexes = self.indexes.read().unwrap();
+        let mut embedded = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let current = row.id.and_then(|id| existing.get(&id));
+            let description = row
+                .description
+                .as_deref()
+                .or(current.map(|(_, description)| description.as_str()))
+                .unwrap_or_default();
+            let unchanged =
+                current.is_some_and(|(name, old)| *name == row.name && old.as_str() == description);
+            if unchanged {
+                embedded.push(None);
+                continue;
+            }
+
+            info!("Embedding row {} of {}", i + 1, rows.len());
+            let description_statements: Vec<&str> = description.split("\n").collect();
+            embedded.push(Some(embed_item(
+                &indexes,
+                &row.name,
+                &description_statements,
+            )?));
+        }
+
+        let mut summary = CsvImportSummary::default();
+        let mut conn = self.pool.write();
+        let tx = conn.transaction()?;
+        let mut containers: HashMap<Vec<String>, i64> = HashMap::new();
+        for (row, embeddings) in rows.iter().zip(&embedded) {
+            let container_id = match &row.container {
+                Some(path) => Some(match containers.get(path) {
+                    Some(&id) => id,
+                    None => {
+                        let id = find_or_create_container(&tx, path, &mut summary)?;
+                        containers.insert(path.clone(), id);
+                        id
+                    }
+                }),
+                None => None,
+            };
+
+            let item_id = match row.id.filter(|id| existing.contains_key(id)) {
+                Some(item_id) => {
+                    let (old_name, old_description) = &existing[&item_id];
+                    let description = row.description.as_ref().unwrap_or(old_description);
+                    if let Some(embeddings) = embeddings {
+                        for (index, embeddings) in embeddings {
+                            delete_embeddings(&tx, item_id, index)?;
+                            insert_embeddings(&tx, item_id, index, embeddings)?;
+                        }
+                        tx.execute(
+                            "UPDATE Items SET name = ?, description = ? WHERE id = ?",
+                            rusqlite::params![row.name, description, item_id],
+                        )?;
+                        tx.execute(
+                            "UPDATE items_fts SET name = ?, description = ? WHERE rowid = ?",
+                            rusqlite::params![row.name, description, item_id],
+                        )?;
+                    }
+                    if *old_name != row.name {
+                        log_history(
+                            &tx,
+                            item_id,
+                            "edit",
+                            Some("name"),
+                            Some(old_name),
+                            Some(&row.name),
+                        )?;
+                    }
+                    if old_description != description {
+                        log_history(
+                            &tx,
+                            item_id,
+                            "edit",
+                            Some("description"),
+                            Some(old_description),
+                            Some(description),
+                        )?;
+                    }
+
+                    if let Some(container_id) = container_id {
+                        let old_container_id: i64 = tx.query_row(
+                            "SELECT contained_by FROM Items WHERE id = ?",
+                            [item_id],
+                            |row| row.get(0),
+                        )?;
+                        if old_container_id != container_id {
+                            let old_location = container_path_text(&tx, old_container_id)?;
+                            tx.execute(
+                                "UPDATE Items SET contained_by = ? WHERE id = ?",
+                                [container_id, item_id],
+                            )?;
+                            let new_location = container_path_text(&tx, container_id)?;
+                            log_history(
+                                &tx,
+                                item_id,
+                                "move",
+                                None,
+                                Some(&old_location),
+                                Some(&new_location),
+                            )?;
+                        }
+                    }
+
+                    summary.updated += 1;
+                    item_id
+                }
+                None => {
+                    let description = row.description.as_deref().unwrap_or_default();
+                    let container_id = container_id.unwrap_or(1);
+                    tx.execute(
+                        r#"INSERT INTO Items(name, description, contained_by, created_at, modified_at)
+                            VALUES (?,?,?,CURRENT_TIMESTAMP,CURRENT_TIMESTAMP)"#,
+                        rusqlite::params![row.name, description, container_id],
+                    )?;
+                    let item_id = tx.last_insert_rowid();
+                    tx.execute(
+                        "INSERT INTO items_fts(rowid, name, description) VALUES (?,?,?)",
+                        rusqlite::params![item_id, row.name, description],
+                    )?;
+                    for (index, embeddings) in embeddings.iter().flatten() {
+                        insert_embeddings(&tx, item_id, index, embeddings)?;
+                    }
+                    let location = container_path_text(&tx, container_id)?;
+                    log_history(&tx, item_id, "create", None, None, Some(&location))?;
+
+                    summary.created += 1;
+                    item_id
+                }
+            };
+
+            if row.quantity.is_some() || row.unit.is_some() {
+                let old_quantity = quantity_text(&tx, item_id)?;
+                if let Some(quantity) = row.quantity {
+                    tx.execute(
+                        "UPDATE Items SET quantity = ? WHERE id = ?",
+                        [quantity.max(0), item_id],
+                    )?;
+                }
+                if let Some(unit) = &row.unit {
+                    tx.execute(
+                        "UPDATE Items SET unit = ? WHERE id = ?",
+                        rusqlite::params![unit, item_id],
+                    )?;
+                }
+                log_quantity_change(&tx, item_id, &old_quantity)?;
+            }
+        }
+        tx.commit()?;
+
+        info!("Imported CSV: {:?}", summary);
+
+        Ok(summary)
+    }
+}
+
+/// The live container at `path` below ROOT, creating any missing containers along it
+fn find_or_create_container(
+    conn: &rusqlite::Connection,
+    path: &[String],
+    summary: &mut CsvImportSummary,
+) -> Result<i64> {
+    let mut container_id = 1;
+    for name in path {
+        let child: Option<i64> = conn
+            .query_row(
+                r#"SELECT id FROM containers
+                    WHERE contained_by = ? AND name = ? AND trash_id IS NULL
+                    ORDER BY id LIMIT 1"#,
+                rusqlite::params![container_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        container_id = match child {
+            Some(id) => id,
+            None => {
+                conn.execute(
+                    "INSERT INTO containers(name, contained_by) VALUES (?,?)",
+                    rusqlite::params![name, container_id],
+                )?;
+                summary.containers_created += 1;
+                conn.last_insert_rowid()
+            }
+        };
+    }
+
+    Ok(container_id)
+}
+
+/// Read item rows, locating columns by the header row so they may come in any order
+fn parse_items(csv: &str) -> Result<Vec<CsvItem>> {
+    let mut records = parse_records(csv)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        bail!("CSV is empty");
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column.trim().eq_ignore_ascii_case(name))
+    };
+    let Some(name_column) = column("name") else {
+        bail!("CSV has no name column");
+    };
+    let id_column = column("id");
+    let description_column = column("description");
+    let container_column = column("container");
+    let quantity_column = column("quantity");
+    let unit_column = column("unit");
+
+    let mut items = Vec::new();
+    for (line, record) in records {
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .map(|field| field.trim())
+        };
+        let present = |column: Option<usize>| field(column).filter(|field| !field.is_empty());
+
+        let name = field(Some(name_column)).unwrap_or_default();
+        if name.is_empty() {
+            bail!("Line {line}: name is empty");
+        }
+        let id = present(id_column)
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("Line {line}: id is not a number"))?;
+        let quantity = present(quantity_column)
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("Line {line}: quantity is not a whole number"))?;
+        let container = present(container_column).map(|path| {
+            if path == ROOT_PATH {
+                Vec::new()
+            } else {
+                split_path(path)
+            }
+        });
+
+        items.push(CsvItem {
+            id,
+            name: name.to_string(),
+            description: present(description_column).map(String::from),
+            container,
+            quantity,
+            unit: present(unit_column).map(String::from),
+        });
+    }
+
+    Ok(items)
+}
+
+/// The container names in a `container` cell. Only the exact separator splits, so names
+/// such as `AC/DC` survive a round trip.
+fn split_path(path: &str) -> Vec<String> {
+    path.split(PATH_SEPARATOR)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Split RFC 4180 CSV into records, each with the line it starts on. Quoted fields may
+/// hold commas, doubled quotes and line breaks.
+fn parse_records(csv: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = csv.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        // CRLF counts once, on its LF
+        if c == '\n' || (c == '\r' && chars.peek() != Some(&'\n')) {
+            line += 1;
+        }
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        bail!("Line {record_line}: quoted field is never closed");
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+fn write_record(csv: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(records: &[(usize, Vec<String>)]) -> Vec<Vec<&str>> {
+        records
+            .iter()
+            .map(|(_, record)| record.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        let records =
+            parse_records("name,description\n\"Drill, cordless\",\"The \"\"big\"\" one\"\n")
+                .unwrap();
+
+        assert_eq!(
+            fields(&records),
+            [
+                vec!["name", "description"],
+                vec!["Drill, cordless", "The \"big\" one"]
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_embedded_newlines() {
+        let records = parse_records("name,description\nTap,\"M3\nM4\"\nDie,M5\n").unwrap();
+
+        assert_eq!(
+            fields(&records),
+            [
+                vec!["name", "description"],
+                vec!["Tap", "M3\nM4"],
+                vec!["Die", "M5"]
+            ]
+        );
+        let lines: Vec<usize> = records.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 2, 4]);
+    }
+
+    #[test]
+    fn parses_crlf_and_lone_cr() {
+        let records = parse_records("\u{feff}name\r\nTap\rDie\r\nVise").unwrap();
+
+        assert_eq!(
+            fields(&records),
+            [vec!["name"], vec!["Tap"], vec!["Die"], vec!["Vise"]]
+        );
+        let lines: Vec<usize> = records.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_unclosed_quote() {
+        assert!(parse_records("name\n\"Tap\n").is_err());
+    }
+
+    #[test]
+    fn splits_paths_on_exact_separator() {
+        assert_eq!(
+            split_path("Garage / AC/DC / 1/4 inch"),
+            ["Garage", "AC/DC", "1/4 inch"]
+        );
+        assert!(split_path("").is_empty());
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let path = ["Garage", "AC/DC", "1/4 inch"].join(PATH_SEPARATOR);
+        let mut csv = String::new();
+        write_record(&mut csv, &HEADER);
+        write_record(
+            &mut csv,
+            &[
+                "7",
+                "Socket, \"deep\"",
+                "Chrome\r\nMetric",
+                &path,
+                "3",
+                "pcs",
+            ],
+        );
+        write_record(&mut csv, &["8", "Tape", "", ROOT_PATH, "1", "m"]);
+
+        let items = parse_items(&csv).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, Some(7));
+        assert_eq!(items[0].name, "Socket, \"deep\"");
+        assert_eq!(items[0].description.as_deref(), Some("Chrome\r\nMetric"));
+        assert_eq!(
+            items[0].container.as_deref(),
+            Some(
+                &[
+                    "Garage".to_string(),
+                    "AC/DC".to_string(),
+                    "1/4 inch".to_string()
+                ][..]
+            )
+        );
+        assert_eq!(items[0].quantity, Some(3));
+        assert_eq!(items[0].unit.as_deref(), Some("pcs"));
+        assert_eq!(items[1].container.as_deref(), Some(&[][..]));
+    }
+
+    #[test]
+    fn empty_cells_leave_values_alone() {
+        let items = parse_items(
+            "id,name,description,container,quantity,unit
+7,Drill,, ,,
+",
+        )
+        .unwrap();
+
+        assert_eq!(items[0].description, None);
+        assert_eq!(items[0].container, None);
+        assert_eq!(items[0].quantity, None);
+        assert_eq!(items[0].unit, None);
+    }
+}
@@ -0,0 +1,541 @@
+use anyhow::{bail, Result};
+use rusqlite::Transaction;
+use tracing::info;
+
+use super::photo_store::PhotoStore;
+
+/// A schema step. Most ignore the photo store, steps that move data out of the database
+/// write their files before changing the schema.
+type Migration = fn(&Transaction, &PhotoStore) -> Result<()>;
+
+/// Schema changes in the order they were introduced. A database at `PRAGMA user_version` N
+/// has had the first N applied. Only ever append to this list.
+const MIGRATIONS: &[Migration] = &[
+    initial_schema,
+    items_fts,
+    embedding_models,
+    vector_indexes,
+    image_index,
+    possible_duplicates,
+    item_quantities,
+    tags,
+    item_attributes,
+    item_history,
+    trash,
+    photo_store,
+    item_photos,
+    item_timestamps,
+    photo_blobs,
+];
+
+/// Version that moves photo blobs out of `Items`. Reaching it frees most of the file, so
+/// the database is compacted once afterwards.
+const PHOTO_BLOBS_VERSION: usize = 15;
+
+/// Bring the database up to the latest schema, one transaction per step.
+/// Returns the resulting schema version.
+#[tracing::instrument(skip(conn, photos))]
+pub fn run(conn: &mut rusqlite::Connection, photos: &PhotoStore) -> Result<usize> {
+    let current: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if current > MIGRATIONS.len() {
+        bail!(
+            "Database schema version {current} is newer than the latest known version {}",
+            MIGRATIONS.len()
+        );
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = i + 1;
+        info!("Migrating database schema to version {version}");
+
+        let tx = conn.transaction()?;
+        migration(&tx, photos)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+
+    // can't run inside a transaction
+    if current < PHOTO_BLOBS_VERSION {
+        info!("Compacting database");
+        conn.execute("VACUUM", [])?;
+    }
+
+    Ok(MIGRATIONS.len())
+}
+
+/// The schema as it was before migrations were tracked. Everything is `IF NOT EXISTS` so
+/// databases created back then are adopted as version 1 untouched.
+fn initial_schema(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS vec_items USING vec0(embedding float[1024])",
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE TABLE IF NOT EXISTS "Items" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL,
+                    "description"	TEXT NOT NULL,
+                    "small_photo"	BLOB NOT NULL,
+                    "large_photo"	BLOB NOT NULL,
+                    "contained_by"  INTEGER NOT NULL,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE TABLE IF NOT EXISTS "containers" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL,
+                    "location"	TEXT,
+                    "contained_by"	INTEGER,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE TABLE IF NOT EXISTS "embedding_to_item" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "embedding_id"	INTEGER NOT NULL,
+                    "item_id"	INTEGER NOT NULL,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE TABLE IF NOT EXISTS "import_log" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "source"	TEXT NOT NULL,
+                    "status"	TEXT NOT NULL,
+                    "target_container"	INTEGER NOT NULL,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                );"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX IF NOT EXISTS "idx_item_container" ON "Items" (
+                    "contained_by"
+                );"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX IF NOT EXISTS "idx_container_container" ON "containers" (
+                    "contained_by"
+                );"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX IF NOT EXISTS "idx_embedding_to_item_item_id" ON "embedding_to_item" (
+                    "item_id"
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX IF NOT EXISTS "idx_embedding_to_item_embedding_id" ON "embedding_to_item" (
+                    "embedding_id"
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"INSERT OR IGNORE INTO containers(id, name) VALUES (1, "ROOT")"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Keyword index over item text. Rebuilt from scratch in case an untracked copy already exists.
+fn items_fts(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute("DROP TABLE IF EXISTS items_fts", [])?;
+    tx.execute(
+        "CREATE VIRTUAL TABLE items_fts USING fts5(name, description)",
+        [],
+    )?;
+    tx.execute(
+        "INSERT INTO items_fts(rowid, name, description) SELECT id, name, description FROM Items",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Registry of which vec0 table holds each embedding model's vectors. Everything before this
+/// was embedded with mxbai-embed-large-v1 into `vec_items`.
+fn embedding_models(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "embedding_models" (
+                    "model"	TEXT NOT NULL UNIQUE,
+                    "dimension"	INTEGER NOT NULL,
+                    "vec_table"	TEXT NOT NULL UNIQUE,
+                    PRIMARY KEY("model")
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"INSERT INTO embedding_models(model, dimension, vec_table)
+                VALUES ("MxbaiEmbedLargeV1", 1024, "vec_items")"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Replace the per-model registry with numbered vector indexes so a model can be rebuilt into
+/// a fresh table while its current one keeps serving searches, and tag every embedding with
+/// the index (and so the model) that produced it.
+fn vector_indexes(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "vector_indexes" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "model"	TEXT NOT NULL,
+                    "dimension"	INTEGER NOT NULL,
+                    "vec_table"	TEXT NOT NULL UNIQUE,
+                    "active"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"INSERT INTO vector_indexes(model, dimension, vec_table)
+                SELECT model, dimension, vec_table FROM embedding_models ORDER BY rowid"#,
+        [],
+    )?;
+
+    // Only one model can have embeddings, so the table with rows is the live one.
+    // With no embeddings at all, the most recently registered model was the one in use.
+    let indexes: Vec<(i64, String)> = tx
+        .prepare("SELECT id, vec_table FROM vector_indexes ORDER BY id DESC")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut active = indexes.first().map(|(id, _)| *id);
+    for (id, vec_table) in &indexes {
+        let has_rows: bool = tx.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM {vec_table})"),
+            [],
+            |row| row.get(0),
+        )?;
+        if has_rows {
+            active = Some(*id);
+            break;
+        }
+    }
+    tx.execute(
+        "UPDATE vector_indexes SET active = (id = ?)",
+        [active.unwrap_or_default()],
+    )?;
+
+    tx.execute("DROP TABLE embedding_models", [])?;
+
+    tx.execute(
+        r#"ALTER TABLE "embedding_to_item" ADD COLUMN "vector_index" INTEGER"#,
+        [],
+    )?;
+    tx.execute(
+        "UPDATE embedding_to_item SET vector_index = (SELECT id FROM vector_indexes WHERE active = 1)",
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX "idx_embedding_to_item_vector_index" ON "embedding_to_item" (
+                    "vector_index",
+                    "embedding_id"
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Which image model produced the per-item photo vectors. The vec0 table itself is sized to
+/// the model, so it is created at startup rather than here.
+fn image_index(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "image_index" (
+                    "model"	TEXT NOT NULL,
+                    "dimension"	INTEGER NOT NULL
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Imported items flagged as looking like an existing item, kept until reviewed
+fn possible_duplicates(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "possible_duplicates" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "import_id"	INTEGER NOT NULL,
+                    "item_id"	INTEGER NOT NULL,
+                    "duplicate_of"	INTEGER NOT NULL,
+                    "similarity"	REAL NOT NULL,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// How many of an item there are, with an optional unit (e.g. "m", "pack")
+fn item_quantities(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"ALTER TABLE "Items" ADD COLUMN "quantity" INTEGER NOT NULL DEFAULT 1"#,
+        [],
+    )?;
+    tx.execute(r#"ALTER TABLE "Items" ADD COLUMN "unit" TEXT"#, [])?;
+
+    Ok(())
+}
+
+/// Free-form labels shared across items, e.g. "consumable"
+fn tags(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "tags" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL UNIQUE,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE TABLE "item_tag" (
+                    "item_id"	INTEGER NOT NULL,
+                    "tag_id"	INTEGER NOT NULL,
+                    PRIMARY KEY("item_id","tag_id")
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Typed key/value facts about an item, one value per key
+fn item_attributes(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "item_attributes" (
+                    "item_id"	INTEGER NOT NULL,
+                    "key"	TEXT NOT NULL,
+                    "value"	TEXT NOT NULL,
+                    "value_type"	TEXT NOT NULL DEFAULT 'text',
+                    PRIMARY KEY("item_id","key")
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Append-only record of item changes. Not tied to `Items` so entries outlive deleted items.
+fn item_history(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "item_history" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "item_id"	INTEGER NOT NULL,
+                    "item_name"	TEXT NOT NULL,
+                    "action"	TEXT NOT NULL,
+                    "field"	TEXT,
+                    "old_value"	TEXT,
+                    "new_value"	TEXT,
+                    "timestamp"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"CREATE INDEX "idx_item_history_item" ON "item_history" (
+                    "item_id"
+                )"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Soft deletion. Trashed items and containers point at their `trash` entry until restored
+/// or purged.
+fn trash(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "trash" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "kind"	TEXT NOT NULL,
+                    "target_id"	INTEGER NOT NULL,
+                    "name"	TEXT NOT NULL,
+                    "location"	TEXT NOT NULL,
+                    "deleted_at"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+
+    tx.execute(r#"ALTER TABLE "Items" ADD COLUMN "trash_id" INTEGER"#, [])?;
+    tx.execute(
+        r#"ALTER TABLE "containers" ADD COLUMN "trash_id" INTEGER"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Photos move out of the `Items` BLOB columns into the photo store, referenced by hash.
+/// Existing blobs are moved by `photo_blobs`.
+fn photo_store(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"ALTER TABLE "Items" ADD COLUMN "small_photo_hash" TEXT"#,
+        [],
+    )?;
+    tx.execute(
+        r#"ALTER TABLE "Items" ADD COLUMN "large_photo_hash" TEXT"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Items can have several photos. The first by `position` is the primary one, used for
+/// thumbnails and photo search.
+fn item_photos(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "item_photos" (
+                    "id"	INTEGER NOT NULL UNIQUE,
+                    "item_id"	INTEGER NOT NULL,
+                    "small_photo_hash"	TEXT NOT NULL,
+                    "large_photo_hash"	TEXT NOT NULL,
+                    "position"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("id" AUTOINCREMENT)
+                )"#,
+        [],
+    )?;
+    tx.execute(
+        r#"CREATE INDEX "item_photos_item" ON "item_photos" ("item_id", "position")"#,
+        [],
+    )?;
+
+    tx.execute(
+        r#"INSERT INTO item_photos(item_id, small_photo_hash, large_photo_hash)
+            SELECT id, small_photo_hash, large_photo_hash FROM Items WHERE large_photo_hash IS NOT NULL"#,
+        [],
+    )?;
+    tx.execute(r#"ALTER TABLE "Items" DROP COLUMN "small_photo_hash""#, [])?;
+    tx.execute(r#"ALTER TABLE "Items" DROP COLUMN "large_photo_hash""#, [])?;
+
+    Ok(())
+}
+
+/// When each item was added and last changed, for sorting container listings. Existing
+/// items take both from their history where there is one.
+fn item_timestamps(tx: &Transaction, _: &PhotoStore) -> Result<()> {
+    tx.execute(r#"ALTER TABLE "Items" ADD COLUMN "created_at" TEXT"#, [])?;
+    tx.execute(r#"ALTER TABLE "Items" ADD COLUMN "modified_at" TEXT"#, [])?;
+    tx.execute(
+        r#"UPDATE Items SET
+            created_at = COALESCE((SELECT MIN(timestamp) FROM item_history WHERE item_id = Items.id), CURRENT_TIMESTAMP),
+            modified_at = COALESCE((SELECT MAX(timestamp) FROM item_history WHERE item_id = Items.id), CURRENT_TIMESTAMP)"#,
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Write photos still stored as `Items` BLOBs to the photo store as the item's primary
+/// photo, then drop the columns. Files are written first, so a failed step leaves only
+/// unreferenced files behind and runs again on the next start.
+fn photo_blobs(tx: &Transaction, photos: &PhotoStore) -> Result<()> {
+    let mut moved = 0;
+    {
+        let mut statement = tx.prepare(
+            "SELECT id, small_photo, large_photo FROM Items WHERE length(large_photo) > 0",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let item_id: i64 = row.get(0)?;
+            let small_photo_hash = photos.put(&row.get::<_, Vec<u8>>(1)?)?;
+            let large_photo_hash = photos.put(&row.get::<_, Vec<u8>>(2)?)?;
+            tx.execute(
+                "INSERT INTO item_photos(item_id, small_photo_hash, large_photo_hash, position) VALUES (?,?,?,-1)",
+                rusqlite::params![item_id, small_photo_hash, large_photo_hash],
+            )?;
+            moved += 1;
+        }
+    }
+    info!("Moved photos of {moved} items to the photo store");
+
+    tx.execute(r#"ALTER TABLE "Items" DROP COLUMN "small_photo""#, [])?;
+    tx.execute(r#"ALTER TABLE "Items" DROP COLUMN "large_photo""#, [])?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo_store() -> (tempfile::TempDir, PhotoStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let photos = PhotoStore::open(dir.path().join("photos")).unwrap();
+        (dir, photos)
+    }
+
+    fn user_version(conn: &rusqlite::Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn empty_database_migrates_to_latest() {
+        crate::database::register_sqlite_vec();
+        let (_dir, photos) = photo_store();
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        assert_eq!(run(&mut conn, &photos).unwrap(), MIGRATIONS.len());
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        // running again is a no-op
+        assert_eq!(run(&mut conn, &photos).unwrap(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn photo_blobs_move_to_store() {
+        crate::database::register_sqlite_vec();
+        let (_dir, photos) = photo_store();
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let before_photo_blobs = MIGRATIONS.len() - 1;
+        for migration in &MIGRATIONS[..before_photo_blobs] {
+            let tx = conn.transaction().unwrap();
+            migration(&tx, &photos).unwrap();
+            tx.commit().unwrap();
+        }
+        conn.pragma_update(None, "user_version", before_photo_blobs)
+            .unwrap();
+        conn.execute(
+            "INSERT INTO Items(id, name, description, small_photo, large_photo, contained_by) VALUES (7, 'Drill', '', x'01', x'0203', 1)",
+            [],
+        )
+        .unwrap();
+
+        run(&mut conn, &photos).unwrap();
+
+        let (small, large): (String, String) = conn
+            .query_row(
+                "SELECT small_photo_hash, large_photo_hash FROM item_photos WHERE item_id = 7",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(photos.get(&small).unwrap(), [1]);
+        assert_eq!(photos.get(&large).unwrap(), [2, 3]);
+        assert!(conn.prepare("SELECT small_photo FROM Items").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+/// Photo files on disk, named by the SHA-256 of their contents so identical photos are
+/// stored once and a name never refers to different bytes
+#[derive(Debug)]
+pub struct PhotoStore {
+    root: PathBuf,
+}
+
+impl PhotoStore {
+    pub fn open(root: PathBuf) -> Result<PhotoStore> {
+        std::fs::create_dir_all(&root)?;
+
+        Ok(PhotoStore { root })
+    }
+
+    /// Store a photo unless it's already present, returning its hash
+    pub fn put(&self, photo: &[u8]) -> Result<String> {
+        let hash = format!("{:x}", Sha256::digest(photo));
+        let path = self.path(&hash)?;
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        // write next to the target and rename, so a crash never leaves a partial file
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(photo)?;
+        file.as_file().sync_all()?;
+        file.persist(&path)?;
+
+        Ok(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>> {
+        Ok(std::fs::read(self.path(hash)?)?)
+    }
+
+    /// Where the photo with this hash lives, sharded by its first two characters
+    pub fn path(&self, hash: &str) -> Result<PathBuf> {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid photo hash: {hash}");
+        }
+
+        Ok(self.root.join(&hash[..2]).join(hash))
+    }
+
+    /// Delete a photo's file. Callers must check no item refers to it anymore.
+    pub fn remove(&self, hash: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(hash)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
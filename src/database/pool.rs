@@ -0,0 +1,101 @@
+use std::{
+    ops::Deref,
+    path::Path,
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+
+/// How long a connection waits on a lock held by another one before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connections to one database file in WAL mode: a single writer, so writes never
+/// contend, and a set of read-only connections that searches and pages run on
+/// concurrently with it.
+pub struct Pool {
+    writer: Mutex<Connection>,
+    readers: Mutex<Vec<Connection>>,
+    reader_returned: Condvar,
+}
+
+/// A read-only connection borrowed from a `Pool`, returned to it on drop
+pub struct Reader<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl Pool {
+    /// Open the writer, switching the database to WAL mode. Readers are opened with
+    /// `open_readers` once migrations have run on the writer.
+    pub fn open(path: &Path) -> Result<Pool> {
+        let writer = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        writer.pragma_update(None, "synchronous", "NORMAL")?;
+        writer.busy_timeout(BUSY_TIMEOUT)?;
+
+        Ok(Pool {
+            writer: Mutex::new(writer),
+            readers: Mutex::new(Vec::new()),
+            reader_returned: Condvar::new(),
+        })
+    }
+
+    pub fn open_readers(&self, path: &Path, count: usize) -> Result<()> {
+        let mut readers = self.readers.lock().unwrap();
+        for _ in 0..count {
+            let reader = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            reader.busy_timeout(BUSY_TIMEOUT)?;
+            readers.push(reader);
+        }
+        self.reader_returned.notify_all();
+
+        Ok(())
+    }
+
+    /// The writer connection. Everything that changes the database goes through it.
+    pub fn write(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Borrow a read-only connection, waiting for one to be returned if all are in use.
+    /// Never hold two at once, or concurrent callers can starve each other.
+    pub fn read(&self) -> Reader<'_> {
+        let mut readers = self.readers.lock().unwrap();
+        loop {
+            if let Some(conn) = readers.pop() {
+                return Reader {
+                    pool: self,
+                    conn: Some(conn),
+                };
+            }
+            readers = self.reader_returned.wait(readers).unwrap();
+        }
+    }
+}
+
+impl Deref for Reader<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.readers.lock().unwrap().push(conn);
+            self.pool.reader_returned.notify_one();
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use rusqlite::OptionalExtension;
+use tracing::info;
+
+use crate::embedding::Embedder;
+
+/// A vec0 table together with the embedder whose vectors it holds
+#[derive(Clone)]
+pub struct VectorIndex {
+    pub id: i64,
+    pub vec_table: String,
+    pub embedder: Arc<dyn Embedder>,
+}
+
+/// The index searches run against, plus the one being rebuilt to replace it, if any
+pub struct VectorIndexes {
+    pub active: VectorIndex,
+    pub pending: Option<VectorIndex>,
+}
+
+impl VectorIndexes {
+    /// Every index that item writes must keep up to date
+    pub fn all(&self) -> impl Iterator<Item = &VectorIndex> {
+        std::iter::once(&self.active).chain(self.pending.as_ref())
+    }
+}
+
+/// Load the active index, discarding anything left over from an interrupted re-index.
+/// When `embedder` is not the active model a pending index is created for it, and
+/// `load_embedder` provides the active model so searches keep working until the swap.
+pub fn load(
+    conn: &rusqlite::Connection,
+    embedder: Arc<dyn Embedder>,
+    load_embedder: impl Fn(&str) -> Result<Arc<dyn Embedder>>,
+) -> Result<VectorIndexes> {
+    let stale: Vec<(i64, String)> = conn
+        .prepare("SELECT id, vec_table FROM vector_indexes WHERE active = 0")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, vec_table) in stale {
+        info!("Dropping unfinished vector index {vec_table}");
+        drop_index(conn, id, &vec_table)?;
+    }
+
+    let active: Option<(i64, String, usize, String)> = conn
+        .query_row(
+            "SELECT id, model, dimension, vec_table FROM vector_indexes WHERE active = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+
+    let Some((id, model, dimension, vec_table)) = active else {
+        return Ok(VectorIndexes {
+            active: create(conn, embedder, true)?,
+            pending: None,
+        });
+    };
+
+    if model == embedder.model_id() {
+        if dimension != embedder.dimension() {
+            bail!(
+                "{vec_table} holds {dimension} dimension vectors but {model} produces {}",
+                embedder.dimension()
+            );
+        }
+
+        return Ok(VectorIndexes {
+            active: VectorIndex {
+                id,
+                vec_table,
+                embedder,
+            },
+            pending: None,
+        });
+    }
+
+    // nothing to migrate, so switch models without loading the old one
+    let has_embeddings: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM embedding_to_item WHERE vector_index = ?)",
+        [id],
+        |row| row.get(0),
+    )?;
+    if !has_embeddings {
+        drop_index(conn, id, &vec_table)?;
+        return Ok(VectorIndexes {
+            active: create(conn, embedder, true)?,
+            pending: None,
+        });
+    }
+
+    info!(
+        "Embedding model changed from {model} to {}, items need re-indexing",
+        embedder.model_id()
+    );
+    Ok(VectorIndexes {
+        active: VectorIndex {
+            id,
+            vec_table,
+            embedder: load_embedder(&model)?,
+        },
+        pending: Some(create(conn, embedder, false)?),
+    })
+}
+
+/// Register a new, empty index for `embedder` with a vec0 table sized to its vectors
+pub fn create(
+    conn: &rusqlite::Connection,
+    embedder: Arc<dyn Embedder>,
+    active: bool,
+) -> Result<VectorIndex> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO vector_indexes(model, dimension, vec_table, active) VALUES (?,?,'',?)",
+        rusqlite::params![embedder.model_id(), embedder.dimension(), active],
+    )?;
+    let id = tx.last_insert_rowid();
+
+    // the id keeps rebuilds of the same model apart, the model name is just for humans
+    let model_name: String = embedder
+        .model_id()
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect();
+    let vec_table = format!("vec_{}_{id}", model_name.to_lowercase());
+
+    tx.execute(
+        "UPDATE vector_indexes SET vec_table = ? WHERE id = ?",
+        rusqlite::params![vec_table, id],
+    )?;
+    tx.execute(
+        &format!(
+            "CREATE VIRTUAL TABLE {vec_table} USING vec0(embedding float[{}])",
+            embedder.dimension()
+        ),
+        [],
+    )?;
+    tx.commit()?;
+
+    Ok(VectorIndex {
+        id,
+        vec_table,
+        embedder,
+    })
+}
+
+/// Make `new` the active index and delete `old` along with its embeddings
+pub fn activate(conn: &rusqlite::Connection, new: &VectorIndex, old: &VectorIndex) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("UPDATE vector_indexes SET active = (id = ?)", [new.id])?;
+    drop_index(&tx, old.id, &old.vec_table)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn drop_index(conn: &rusqlite::Connection, id: i64, vec_table: &str) -> Result<()> {
+    conn.execute("DELETE FROM embedding_to_item WHERE vector_index = ?", [id])?;
+    conn.execute(&format!("DROP TABLE IF EXISTS {vec_table}"), [])?;
+    conn.execute("DELETE FROM vector_indexes WHERE id = ?", [id])?;
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use anyhow::{bail, Result};
+use fastembed::{
+    EmbeddingModel, ImageEmbedding, ImageEmbeddingModel, ImageInitOptions, InitOptions,
+    TextEmbedding,
+};
+
+/// Model used when `EMBEDDING_MODEL` is not set
+const DEFAULT_MODEL: EmbeddingModel = EmbeddingModel::MxbaiEmbedLargeV1;
+
+/// Model used when `IMAGE_EMBEDDING_MODEL` is not set
+const DEFAULT_IMAGE_MODEL: ImageEmbeddingModel = ImageEmbeddingModel::ClipVitB32;
+
+/// Turns text into vectors for the vector index
+pub trait Embedder: Send + Sync {
+    /// Stable identifier recorded alongside the vectors this embedder produces
+    fn model_id(&self) -> &str;
+
+    /// Length of every vector this embedder produces
+    fn dimension(&self) -> usize;
+
+    /// Prepended to search queries
+    fn query_prefix(&self) -> &str;
+
+    /// Prepended to item text before it is indexed
+    fn document_prefix(&self) -> &str;
+
+    /// Embed already prefixed text
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        let Some(embedding) = self
+            .embed(vec![format!("{}{query}", self.query_prefix())])?
+            .pop()
+        else {
+            bail!("No embedding generated for query");
+        };
+
+        Ok(embedding)
+    }
+
+    fn embed_documents(&self, documents: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.embed(
+            documents
+                .iter()
+                .map(|document| format!("{}{document}", self.document_prefix()))
+                .collect(),
+        )
+    }
+}
+
+/// Local ONNX text embedding via fastembed
+pub struct FastEmbedder {
+    model: TextEmbedding,
+    model_id: String,
+    dimension: usize,
+    query_prefix: &'static str,
+    document_prefix: &'static str,
+}
+
+impl FastEmbedder {
+    /// Load the model named by `EMBEDDING_MODEL`, falling back to mxbai-embed-large-v1
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("EMBEDDING_MODEL") {
+            Ok(name) => Self::from_name(&name),
+            Err(_) => Self::new(DEFAULT_MODEL),
+        }
+    }
+
+    /// Load a model by its fastembed name, e.g. `BGESmallENV15`. This is also its `model_id`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        let Some(info) = TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|info| format!("{:?}", info.model).eq_ignore_ascii_case(name))
+        else {
+            bail!("Unsupported embedding model: {name}");
+        };
+
+        Self::new(info.model)
+    }
+
+    pub fn new(model: EmbeddingModel) -> Result<Self> {
+        let dimension = TextEmbedding::get_model_info(&model)?.dim;
+        let (query_prefix, document_prefix) = prefixes(&model);
+
+        let fastembed_opts = InitOptions::new(model.clone());
+
+        #[cfg(feature = "docker")]
+        let fastembed_opts = fastembed_opts.with_cache_dir(std::path::PathBuf::from("/cache"));
+
+        Ok(Self {
+            model: TextEmbedding::try_new(fastembed_opts)?,
+            model_id: format!("{model:?}"),
+            dimension,
+            query_prefix,
+            document_prefix,
+        })
+    }
+}
+
+impl Embedder for FastEmbedder {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn query_prefix(&self) -> &str {
+        self.query_prefix
+    }
+
+    fn document_prefix(&self) -> &str {
+        self.document_prefix
+    }
+
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.model.embed(texts, None)
+    }
+}
+
+/// Turns photos into vectors for searching by photo
+pub trait ImageEmbedder: Send + Sync {
+    /// Stable identifier recorded alongside the vectors this embedder produces
+    fn model_id(&self) -> &str;
+
+    /// Length of every vector this embedder produces
+    fn dimension(&self) -> usize;
+
+    /// Embed encoded images in any format the `image` crate can decode
+    fn embed_images(&self, images: &[&[u8]]) -> Result<Vec<Vec<f32>>>;
+}
+
+/// Local ONNX image embedding via fastembed
+pub struct FastImageEmbedder {
+    model: ImageEmbedding,
+    model_id: String,
+    dimension: usize,
+}
+
+impl FastImageEmbedder {
+    /// Load the model named by `IMAGE_EMBEDDING_MODEL` (a fastembed image model name such as
+    /// `NomicEmbedVisionV15`), falling back to CLIP ViT-B/32.
+    pub fn from_env() -> Result<Self> {
+        let model = match std::env::var("IMAGE_EMBEDDING_MODEL") {
+            Ok(name) => {
+                let Some(info) = ImageEmbedding::list_supported_models()
+                    .into_iter()
+                    .find(|info| format!("{:?}", info.model).eq_ignore_ascii_case(&name))
+                else {
+                    bail!("Unsupported image embedding model: {name}");
+                };
+                info.model
+            }
+            Err(_) => DEFAULT_IMAGE_MODEL,
+        };
+
+        Self::new(model)
+    }
+
+    pub fn new(model: ImageEmbeddingModel) -> Result<Self> {
+        let dimension = ImageEmbedding::get_model_info(&model).dim;
+
+        let fastembed_opts = ImageInitOptions::new(model.clone());
+
+        #[cfg(feature = "docker")]
+        let fastembed_opts = fastembed_opts.with_cache_dir(std::path::PathBuf::from("/cache"));
+
+        Ok(Self {
+            model: ImageEmbedding::try_new(fastembed_opts)?,
+            model_id: format!("{model:?}"),
+            dimension,
+        })
+    }
+}
+
+impl ImageEmbedder for FastImageEmbedder {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed_images(&self, images: &[&[u8]]) -> Result<Vec<Vec<f32>>> {
+        // fastembed only reads images from disk and picks the decoder by file extension
+        let mut files = Vec::new();
+        for image in images {
+            let file = tempfile::Builder::new().suffix(".jpg").tempfile()?;
+            image::load_from_memory(image)?
+                .to_rgb8()
+                .save_with_format(file.path(), image::ImageFormat::Jpeg)?;
+            files.push(file);
+        }
+
+        self.model
+            .embed(files.iter().map(|file| file.path()).collect(), None)
+    }
+}
+
+/// Query and document prefixes each model family was trained with
+fn prefixes(model: &EmbeddingModel) -> (&'static str, &'static str) {
+    match model {
+        EmbeddingModel::MxbaiEmbedLargeV1
+        | EmbeddingModel::MxbaiEmbedLargeV1Q
+        | EmbeddingModel::BGEBaseENV15
+        | EmbeddingModel::BGEBaseENV15Q
+        | EmbeddingModel::BGELargeENV15
+        | EmbeddingModel::BGELargeENV15Q
+        | EmbeddingModel::BGESmallENV15
+        | EmbeddingModel::BGESmallENV15Q => (
+            "Represent this sentence for searching relevant passages: ",
+            "",
+        ),
+        EmbeddingModel::NomicEmbedTextV1
+        | EmbeddingModel::NomicEmbedTextV15
+        | EmbeddingModel::NomicEmbedTextV15Q => ("search_query: ", "search_document: "),
+        EmbeddingModel::MultilingualE5Small
+        | EmbeddingModel::MultilingualE5Base
+        | EmbeddingModel::MultilingualE5Large => ("query: ", "passage: "),
+        _ => ("", ""),
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    io::{BufReader, BufWriter, Read, Seek, Write},
+    io::{BufReader, BufWriter, Seek, Write},
     sync::{Arc, Mutex},
     time::Duration,
 };
@@ -16,37 +16,34 @@ use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIter
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::database::Database;
+use crate::database::{Attribute, AttributeType, Database, ItemDetails, NewPhoto};
+
+/// New items at least this similar to an existing item are flagged as possible duplicates
+const DUPLICATE_SIMILARITY: f64 = 0.85;
 
 pub struct ImportRequest {
-    pub source: String,
-    pub file: std::fs::File,
+    /// Photos or zip archives of photos
+    pub files: Vec<std::fs::File>,
     pub target_container: i64,
+    /// Whether all photos show one item, rather than one item each
+    pub single_item: bool,
 }
 
 pub struct Importer {
-    db_conn: Arc<Database>,
     queue: UnboundedSender<(i64, ImportRequest)>,
 }
 
 impl Importer {
     pub async fn new(db: Arc<Database>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-        tokio::spawn(process_queue(db.clone(), rx));
-        Self {
-            db_conn: db,
-            queue: tx,
-        }
+        tokio::spawn(process_queue(db, rx));
+        Self { queue: tx }
     }
 
-    pub fn add_to_queue(&self, request: ImportRequest) -> Result<()> {
-        let log_id = self.db_conn.log_new_import(
-            &request.source,
-            "Added to queue",
-            request.target_container,
-        )?;
+    /// Queue a request already recorded in the import log as `log_id`
+    pub fn add_to_queue(&self, log_id: i64, request: ImportRequest) -> Result<()> {
         Ok(self.queue.send((log_id, request))?)
     }
 }
@@ -62,7 +59,7 @@ impl ImageFileReader {
         {
             if image_reader.decode().is_ok() {
                 file.seek(std::io::SeekFrom::Start(0)).unwrap();
-                return Ok(Self(Mutex::new(file)));
+                Ok(Self(Mutex::new(file)))
             } else {
                 bail!("Failed to decode");
             }
@@ -107,49 +104,56 @@ async fn process_queue(db: Arc<Database>, mut rx: UnboundedReceiver<(i64, Import
 
     while let Some((log_id, request)) = rx.recv().await {
         info!("New file in queue");
-        db.update_import(log_id, "Starting").unwrap();
+        record_status(&db, log_id, move |db| db.update_import(log_id, "Starting")).await;
         let mut image_queue: Vec<ImageFileReader> = Vec::new();
 
-        // try to process as zip
-        if let Ok(mut archive) = zip::ZipArchive::new(&request.file) {
-            for i in 0..archive.len() {
-                info!("Extracting {} of {}", i + 1, archive.len());
-                if let Ok(photo) = archive.by_index(i) {
-                    let photo_name = photo.name().to_owned();
-                    if photo.is_file() {
-                        let photo_file = tempfile::tempfile().unwrap();
-                        let mut photo_file = BufWriter::new(photo_file);
-                        for byte in photo.bytes() {
-                            photo_file.write_all(&[byte.unwrap()]).unwrap()
-                        }
-                        photo_file.flush().unwrap();
-                        let mut photo_file = photo_file.into_inner().unwrap();
-                        photo_file.seek(std::io::SeekFrom::Start(0)).unwrap();
-
-                        match ImageFileReader::new(photo_file) {
-                            Ok(photo_file) => image_queue.push(photo_file),
-                            Err(e) => error!(
-                                "Encountered: {} on {} ({})",
-                                e.to_string(),
-                                i + 1,
-                                photo_name
-                            ),
+        for file in request.files {
+            // try to process as zip
+            if let Ok(mut archive) = zip::ZipArchive::new(&file) {
+                for i in 0..archive.len() {
+                    info!("Extracting {} of {}", i + 1, archive.len());
+                    if let Ok(mut photo) = archive.by_index(i) {
+                        let photo_name = photo.name().to_owned();
+                        if photo.is_file() {
+                            let photo_file = tempfile::tempfile().unwrap();
+                            let mut photo_file = BufWriter::new(photo_file);
+                            std::io::copy(&mut photo, &mut photo_file).unwrap();
+                            photo_file.flush().unwrap();
+                            let mut photo_file = photo_file.into_inner().unwrap();
+                            photo_file.seek(std::io::SeekFrom::Start(0)).unwrap();
+
+                            match ImageFileReader::new(photo_file) {
+                                Ok(photo_file) => image_queue.push(photo_file),
+                                Err(e) => error!(
+                                    "Encountered: {} on {} ({})",
+                                    e.to_string(),
+                                    i + 1,
+                                    photo_name
+                                ),
+                            }
+                        } else {
+                            error!("Entry {} is not a file ({})", i + 1, photo_name);
                         }
                     } else {
-                        error!("Entry {} is not a file ({})", i + 1, photo_name);
+                        error!("Failed to extract {}", i + 1);
                     }
-                } else {
-                    error!("Failed to extract {}", i + 1);
                 }
-            }
-        } else {
-            // try to process as image
-            match ImageFileReader::new(request.file) {
-                Ok(photo_file) => image_queue.push(photo_file),
-                Err(e) => error!("Single image {}", e.to_string()),
+            } else {
+                // try to process as image
+                match ImageFileReader::new(file) {
+                    Ok(photo_file) => image_queue.push(photo_file),
+                    Err(e) => error!("Single image {}", e.to_string()),
+                }
             }
         }
 
+        // which photos of the queue make up each item, in upload order
+        let items: Vec<Vec<usize>> = if request.single_item && !image_queue.is_empty() {
+            vec![(0..image_queue.len()).collect()]
+        } else {
+            (0..image_queue.len()).map(|i| vec![i]).collect()
+        };
+
         let image_queue = Arc::new(image_queue);
         let resize_image_queue = image_queue.clone();
         let resize_job = tokio::task::spawn_blocking(move || {
@@ -158,40 +162,44 @@ async fn process_queue(db: Arc<Database>, mut rx: UnboundedReceiver<(i64, Import
                 .enumerate()
                 .map(|(i, image_reader)| {
                     info!("Starting resize {}", i + 1);
-                    let photo_resized_large = downscale_image(&image_reader, 1024);
-                    let photo_resized_small = downscale_image(&image_reader, 512);
+                    let photo = resize_photo(image_reader);
                     info!("Done resize {}", i + 1);
-                    (photo_resized_small, photo_resized_large)
+                    Some(photo)
                 })
-                .collect::<Vec<(ImageFileReader, ImageFileReader)>>()
+                .collect::<Vec<Option<NewPhoto>>>()
         });
 
         let mut openai_item_info = Vec::new();
-        let image_queue_len = image_queue.len();
-        for i in 0..image_queue_len {
+        for (i, item_photos) in items.iter().enumerate() {
             let client = client.clone();
             let openai_image_queue = image_queue.clone();
+            let item_photos = item_photos.clone();
             openai_item_info.push(tokio::spawn(async move {
-                let mut photo_data = Vec::new();
-                openai_image_queue[i]
-                    .to_image()
-                    .to_rgb8()
-                    .write_to(
-                        &mut std::io::Cursor::new(&mut photo_data),
-                        image::ImageFormat::Jpeg,
-                    )
-                    .unwrap();
-
-                let photo_b64 = base64::display::Base64Display::new(
-                    &photo_data,
-                    &base64::engine::general_purpose::STANDARD,
-                )
-                .to_string();
+                let photos_b64: Vec<String> = item_photos
+                    .iter()
+                    .map(|photo| {
+                        let mut photo_data = Vec::new();
+                        openai_image_queue[*photo]
+                            .to_image()
+                            .to_rgb8()
+                            .write_to(
+                                &mut std::io::Cursor::new(&mut photo_data),
+                                image::ImageFormat::Jpeg,
+                            )
+                            .unwrap();
+
+                        base64::display::Base64Display::new(
+                            &photo_data,
+                            &base64::engine::general_purpose::STANDARD,
+                        )
+                        .to_string()
+                    })
+                    .collect();
 
                 info!("Starting openai request {}", i + 1);
                 let mut item_info = None;
                 for retry in 0..10 {
-                    match get_description(&client, &photo_b64).await {
+                    match get_description(&client, &photos_b64).await {
                         Ok(info) => {
                             item_info = Some(info);
                             break;
@@ -210,40 +218,144 @@ async fn process_queue(db: Arc<Database>, mut rx: UnboundedReceiver<(i64, Import
             }));
         }
 
-        let resized_results = resize_job.await.unwrap();
-        for ((resized_small, resized_large), openai_info) in resized_results
-            .into_iter()
-            .zip(openai_item_info.into_iter())
-        {
-            if let Some(item_info) = openai_info.await.unwrap() {
-                let resized_small: Vec<u8> = resized_small.into();
-                let resized_large: Vec<u8> = resized_large.into();
-                db.insert_item(
-                    &item_info.name,
-                    &item_info.descriptions,
-                    &resized_small,
-                    &resized_large,
-                    request.target_container,
-                )
-                .unwrap();
-            } else {
+        let mut resized_results = resize_job.await.unwrap();
+        let mut possible_duplicates = 0;
+        let mut failed = 0;
+        for (item_photos, openai_info) in items.iter().zip(openai_item_info) {
+            let Ok(Some(item_info)) = openai_info.await else {
                 error!("Failed to import");
+                failed += 1;
+                continue;
+            };
+            let photos: Vec<NewPhoto> = item_photos
+                .iter()
+                .filter_map(|photo| resized_results[*photo].take())
+                .collect();
+            let db = db.clone();
+            let target_container = request.target_container;
+            let stored = tokio::task::spawn_blocking(move || {
+                store_item(&db, log_id, target_container, item_info, &photos)
+            })
+            .await;
+            match stored {
+                Ok(Ok(duplicates)) => possible_duplicates += duplicates,
+                Ok(Err(e)) => {
+                    error!("Failed to store item: {}", e);
+                    failed += 1;
+                }
+                Err(e) => {
+                    error!("Storing item panicked: {}", e);
+                    failed += 1;
+                }
             }
         }
 
-        db.update_import(log_id, "Complete").unwrap();
+        // a failed import is recorded and the queue moves on to the next one
+        if failed > 0 {
+            let reason = format!("FAILED, {failed} of {} items", items.len());
+            record_status(&db, log_id, move |db| {
+                db.cancel_import(log_id, Some(&reason))
+            })
+            .await;
+        } else if possible_duplicates > 0 {
+            let status = format!("Complete, {possible_duplicates} possible duplicates");
+            record_status(&db, log_id, move |db| db.update_import(log_id, &status)).await;
+        } else {
+            record_status(&db, log_id, move |db| db.update_import(log_id, "Complete")).await;
+        }
     }
 }
 
+/// Write an import's status on the blocking pool, as the writer may be held by a long
+/// write elsewhere. Failures are only logged so the queue keeps going.
+async fn record_status(
+    db: &Arc<Database>,
+    log_id: i64,
+    update: impl FnOnce(&Database) -> Result<()> + Send + 'static,
+) {
+    let db = db.clone();
+    match tokio::task::spawn_blocking(move || update(&db)).await {
+        Ok(Ok(())) => (),
+        Ok(Err(e)) => error!("Failed to update import {}: {}", log_id, e),
+        Err(e) => error!("Updating import {} panicked: {}", log_id, e),
+    }
+}
+
+/// Insert an imported item with its attributes and log any likely duplicates of it.
+/// Blocks on embedding, so it runs off the async runtime. Returns the duplicates found.
+fn store_item(
+    db: &Database,
+    log_id: i64,
+    target_container: i64,
+    item_info: ItemInfo,
+    photos: &[NewPhoto],
+) -> Result<usize> {
+    // check before inserting so the new item doesn't match itself
+    let duplicates = db
+        .find_duplicates(
+            &item_info.name,
+            &item_info.descriptions,
+            DUPLICATE_SIMILARITY,
+        )
+        .unwrap_or_else(|e| {
+            error!("Duplicate check failed: {}", e);
+            Vec::new()
+        });
+
+    let details = ItemDetails {
+        quantity: item_info.quantity.max(1),
+        unit: item_info.unit,
+        attributes: item_info
+            .attributes
+            .iter()
+            .map(|attribute| {
+                Attribute::new(
+                    &attribute.key,
+                    &attribute.value,
+                    AttributeType::from_name(&attribute.value_type),
+                )
+            })
+            .collect(),
+    };
+    let item_id = db.insert_item(
+        &item_info.name,
+        &item_info.descriptions,
+        photos,
+        target_container,
+        &details,
+    )?;
+
+    for duplicate in &duplicates {
+        warn!(
+            "{} looks like existing item {} ({}) in {}",
+            item_info.name, duplicate.id, duplicate.name, duplicate.container_name
+        );
+        db.log_possible_duplicate(log_id, item_id, duplicate.id, duplicate.similarity)?;
+    }
+
+    Ok(duplicates.len())
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct ItemInfo {
     name: String,
     descriptions: Vec<String>,
+    quantity: i64,
+    unit: Option<String>,
+    attributes: Vec<ItemAttribute>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ItemAttribute {
+    key: String,
+    value: String,
+    value_type: String,
 }
 
+/// Describe the item shown in one or more photos, given as base64 JPEGs
 async fn get_description(
     client: &async_openai::Client<async_openai::config::OpenAIConfig>,
-    photo_b64: &str,
+    photos_b64: &[String],
 ) -> Result<ItemInfo> {
     let schema = json!({
         "type": "object",
@@ -258,11 +370,45 @@ async fn get_description(
                 "type": "string",
             },
             "description": "A series of statements giving a full and detailed description of what you see in this image, including all text you can read."
+        },
+        "quantity": {
+            "type": "integer",
+            "description": "How many of the object are in the image."
+        },
+        "unit": {
+            "type": ["string", "null"],
+            "description": "The unit the quantity is counted in, such as pack or m, or null for individual pieces."
+        },
+        "attributes": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "key": {
+                        "type": "string",
+                        "description": "What is measured, e.g. voltage, wattage, thread pitch, size."
+                    },
+                    "value": {
+                        "type": "string",
+                        "description": "The value including its unit, e.g. 12V."
+                    },
+                    "value_type": {
+                        "type": "string",
+                        "enum": ["text", "number", "boolean"]
+                    }
+                },
+                "required": ["key", "value", "value_type"],
+                "additionalProperties": false
+            },
+            "description": "Structured technical facts that can be read or reliably inferred from the object."
         }
         },
         "required": [
         "name",
-        "descriptions"
+        "descriptions",
+        "quantity",
+        "unit",
+        "attributes"
         ],
         "additionalProperties": false
     });
@@ -276,35 +422,61 @@ async fn get_description(
         },
     };
 
-    let  request = CreateChatCompletionRequestArgs::default()
+    let mut content = Vec::new();
+    if photos_b64.len() > 1 {
+        content.push(
+            ChatCompletionRequestMessageContentPartTextArgs::default()
+                .text("All of these photos show the same object from different sides. Combine what you see in all of them.")
+                .build()?
+                .into(),
+        );
+    }
+    content.extend([
+        ChatCompletionRequestMessageContentPartTextArgs::default()
+            .text("Please give a short name for this object.")
+            .build()?
+            .into(),
+        ChatCompletionRequestMessageContentPartTextArgs::default()
+            .text("Please give a full and detailed description of what you see in this image. Include all text you can read. Give the description as a series of statements. Do not mention the background or any human hands.")
+            .build()?
+            .into(),
+        ChatCompletionRequestMessageContentPartTextArgs::default()
+            .text("Please estimate how many of this object are shown. If the packaging states a count or length, use that together with its unit.")
+            .build()?
+            .into(),
+        ChatCompletionRequestMessageContentPartTextArgs::default()
+            .text("Please list technical attributes such as voltage, wattage, thread pitch or size as key/value pairs. Only include attributes you can read or are confident about.")
+            .build()?
+            .into(),
+    ]);
+    for photo_b64 in photos_b64 {
+        content.push(
+            ChatCompletionRequestMessageContentPartImageArgs::default()
+                .image_url(
+                    ImageUrlArgs::default()
+                        .url(format!("data:image/jpeg;base64,{}", photo_b64))
+                        .detail(async_openai::types::ImageDetail::High)
+                        .build()?,
+                )
+                .build()?
+                .into(),
+        );
+    }
+
+    let request = CreateChatCompletionRequestArgs::default()
         .model("gpt-4o-mini")
         .max_tokens(1000_u32)
-        .messages([ChatCompletionRequestSystemMessage::from(
-            "You are a helpful item identifier and describer. You always respond in valid JSON.",
-        )
-        .into(),
-        ChatCompletionRequestUserMessageArgs::default()
-            .content(vec![
-                ChatCompletionRequestMessageContentPartTextArgs::default()
-                    .text("Please give a short name for this object.")
-                    .build()?
-                    .into(),
-                ChatCompletionRequestMessageContentPartTextArgs::default()
-                    .text("Please give a full and detailed description of what you see in this image. Include all text you can read. Give the description as a series of statements. Do not mention the background or any human hands.")
-                    .build()?
-                    .into(),
-                ChatCompletionRequestMessageContentPartImageArgs::default()
-                    .image_url(
-                        ImageUrlArgs::default()
-                            .url(format!("data:image/jpeg;base64,{}",photo_b64))
-                            .detail(async_openai::types::ImageDetail::High)
-                            .build()?,
-                    )
-                    .build()?
-                    .into(),
-            ])
-            .build()?
-            .into()]).response_format(response_format)
+        .messages([
+            ChatCompletionRequestSystemMessage::from(
+                "You are a helpful item identifier and describer. You always respond in valid JSON.",
+            )
+            .into(),
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(content)
+                .build()?
+                .into(),
+        ])
+        .response_format(response_format)
         .build()?;
 
     let response = client.chat().create(request).await?;
@@ -315,6 +487,18 @@ async fn get_description(
     Ok(item_info)
 }
 
+/// Decode an uploaded photo and scale it to the sizes items store. Blocks on decoding.
+pub fn prepare_photo(file: std::fs::File) -> Result<NewPhoto> {
+    Ok(resize_photo(&ImageFileReader::new(file)?))
+}
+
+fn resize_photo(image_reader: &ImageFileReader) -> NewPhoto {
+    NewPhoto {
+        small: downscale_image(image_reader, 512).into(),
+        large: downscale_image(image_reader, 1024).into(),
+    }
+}
+
 fn calculate_new_dimensions(width: u32, height: u32, max_dimension: u32) -> (u32, u32) {
     if width > height {
         // Landscape orientation or square
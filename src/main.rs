@@ -7,10 +7,10 @@ use std::{
 
 use anyhow::{bail, Result};
 use axum::{
-    body::Bytes,
-    extract::{DefaultBodyLimit, Multipart, Path, State},
-    http::StatusCode,
-    response::{Html, Response},
+    body::Body,
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
     routing::{delete, get, post},
     Form, Router,
 };
@@ -21,7 +21,9 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use zerocopy::IntoBytes;
 
 mod database;
+mod embedding;
 mod import;
+mod reindex;
 
 lazy_static::lazy_static! {
     pub static ref TEMPLATES: minijinja::Environment<'static> = {
@@ -33,9 +35,44 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Entries shown on the recent changes page
+const RECENT_CHANGES_LIMIT: i64 = 200;
+
+/// Items loaded at a time as a container's grid is scrolled
+const CONTAINER_PAGE_SIZE: i64 = 48;
+
+/// Days trashed items are kept before being purged, unless `TRASH_RETENTION_DAYS` is set
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+/// A photo's file never changes, so it may be cached for a year
+const PHOTO_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// An item's primary photo can be swapped, so browsers must revalidate it by its ETag
+const PRIMARY_PHOTO_CACHE_CONTROL: &str = "no-cache";
+
+/// Shown in place of the thumbnail of items added without a photo
+const NO_PHOTO_PLACEHOLDER: &str = "/assets/img/no-photo.svg";
+
+/// How often expired trash is purged
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 struct AppState {
     database: Arc<database::Database>,
     importer: Arc<Mutex<import::Importer>>,
+    reindexer: reindex::Reindexer,
+    trash_retention_days: i64,
+}
+
+impl AppState {
+    /// Run a database call on the blocking thread pool, so slow queries and embedding
+    /// don't stall other requests
+    async fn db<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&database::Database) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let database = self.database.clone();
+        tokio::task::spawn_blocking(move || f(&database)).await?
+    }
 }
 
 impl Debug for AppState {
@@ -50,11 +87,39 @@ struct CreateContainer {
     parent_container_id: i64,
 }
 
+/// Query string of a container's item listing
+#[derive(Debug, Deserialize)]
+struct ContainerListing {
+    sort: Option<String>,
+    /// Include items in nested containers
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    offset: i64,
+}
+
+impl ContainerListing {
+    fn sort(&self) -> Result<database::ItemSort> {
+        match &self.sort {
+            Some(sort) => database::ItemSort::from_name(sort),
+            None => Ok(database::ItemSort::default()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateItem {
+    container_id: i64,
+    name: String,
+    description: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct EditItem {
     new_name: String,
-    new_location: String,
     new_description: String,
+    new_quantity: i64,
+    new_unit: String,
 }
 
 #[tokio::main]
@@ -63,10 +128,6 @@ async fn main() -> Result<()> {
         warn!(".env file not found, falling back to env variables");
     }
 
-    if std::env::var("OPENAI_API_KEY").is_err() {
-        bail!("Environment variable: OPENAI_API_KEY not found");
-    }
-
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -76,19 +137,105 @@ async fn main() -> Result<()> {
         .init();
 
     info!("Initializing Database");
-    let db = Arc::new(database::Database::init()?);
+    let embedder = Arc::new(embedding::FastEmbedder::from_env()?);
+    let image_embedder = Arc::new(embedding::FastImageEmbedder::from_env()?);
+    let db = Arc::new(database::Database::init(
+        embedder,
+        |model_id| Ok(Arc::new(embedding::FastEmbedder::from_name(model_id)?)),
+        image_embedder,
+    )?);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => (),
+        [command, path] if command == "backup" => {
+            db.export_backup(std::fs::File::create(path)?)?;
+            return Ok(());
+        }
+        [command, path] if command == "restore" => {
+            db.restore_backup(std::fs::File::open(path)?)?;
+            return Ok(());
+        }
+        _ => bail!("Usage: stuff-search [backup <file.zip> | restore <file.zip>]"),
+    }
+
+    if std::env::var("OPENAI_API_KEY").is_err() {
+        bail!("Environment variable: OPENAI_API_KEY not found");
+    }
+
+    let image_db = db.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = image_db.embed_missing_images() {
+            error!("Failed to build image index: {}", e);
+        }
+    });
+
+    match std::env::var("CHECK_ON_STARTUP").as_deref() {
+        Ok("repair") => {
+            db.check_and_repair()?;
+        }
+        Ok("report") => {
+            let report = db.check()?;
+            if !report.is_clean() {
+                warn!("Database check found problems: {:?}", report);
+            }
+        }
+        _ => (),
+    }
+
+    let retention_days = match std::env::var("TRASH_RETENTION_DAYS") {
+        Ok(days) => days.parse::<i64>()?,
+        Err(_) => DEFAULT_TRASH_RETENTION_DAYS,
+    };
+    let trash_db = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let db = trash_db.clone();
+            match tokio::task::spawn_blocking(move || db.purge_trash(retention_days)).await {
+                Ok(Err(e)) => error!("Failed to purge trash: {}", e),
+                Err(e) => error!("Trash purge panicked: {}", e),
+                Ok(Ok(())) => (),
+            }
+        }
+    });
+
+    let reindexer = reindex::Reindexer::new(db.clone());
+    if db.reindex_pending() {
+        reindexer.start()?;
+    }
 
     let importer = Arc::new(Mutex::new(import::Importer::new(db.clone()).await));
     let shared_state = Arc::new(AppState {
         database: db,
         importer,
+        reindexer,
+        trash_retention_days: retention_days,
     });
 
     let app = Router::new()
         .route("/", get(serve_index))
         .route("/page/search", get(serve_search))
+        .route("/page/admin", get(serve_admin))
+        .route("/page/duplicates", get(serve_duplicates))
+        .route("/page/history", get(serve_history))
+        .route("/page/trash", get(serve_trash))
+        .route("/trash/{id}/restore", post(restore_trash))
+        .route("/trash/{id}", delete(purge_trash))
+        .route("/duplicates/{id}", delete(dismiss_duplicate))
+        .route("/reindex", post(start_reindex))
+        .route("/reindex/status", get(reindex_status))
+        .route("/admin/check", post(check_database))
+        .route("/admin/repair/{kind}", post(repair_database))
+        .route("/admin/backup", get(export_backup))
+        .route("/admin/restore", post(restore_backup))
+        .route("/admin/items.csv", get(export_csv))
+        .route("/admin/items.csv", post(import_csv))
         .route("/search", post(search))
+        .route("/search/image", post(search_image))
         .route("/container/{id}", get(container))
+        .route("/container/{id}/items", get(container_items))
         .route("/container/{id}/create", get(container_create_child))
         .route("/container/{id}/rename", get(get_container_rename))
         .route("/container/{id}/rename", post(handle_container_rename))
@@ -101,9 +248,29 @@ async fn main() -> Result<()> {
         .route("/container/{id}/confirm", delete(delete_container))
         .route("/modal/upload/{id}", get(modal_upload))
         .route("/upload", post(upload))
+        .route("/modal/item/new/{container_id}", get(modal_new_item))
+        .route("/item/create", post(create_item))
         .route("/modal/item/{id}/show", get(modal_item_show))
         .route("/model/item/{id}/edit", get(get_modal_item_edit))
         .route("/model/item/{id}/edit", post(handle_modal_item_edit))
+        .route(
+            "/item/{id}/quantity/increment",
+            post(increment_item_quantity),
+        )
+        .route(
+            "/item/{id}/quantity/decrement",
+            post(decrement_item_quantity),
+        )
+        .route("/item/{id}/tags", post(add_item_tag))
+        .route("/item/{id}/tags/remove", post(remove_item_tag))
+        .route("/item/{id}/attributes", post(set_item_attribute))
+        .route("/item/{id}/attributes/remove", post(remove_item_attribute))
+        .route("/item/{id}/photos", post(add_item_photos))
+        .route(
+            "/item/{id}/photos/{photo_id}/primary",
+            post(set_primary_item_photo),
+        )
+        .route("/item/{id}/photos/{photo_id}", delete(remove_item_photo))
         .route("/item/{i}", delete(delete_item_unconfirmed))
         .route("/item/{i}/confirm", delete(delete_item))
         .route("/item/move/{item_id}/{container_id}", post(move_item))
@@ -113,6 +280,8 @@ async fn main() -> Result<()> {
         )
         .route("/images/small/{id}/small.jpg", get(small_photo))
         .route("/images/large/{id}/large.jpg", get(large_photo))
+        .route("/images/photo/{id}/small.jpg", get(small_item_photo))
+        .route("/images/photo/{id}/large.jpg", get(large_item_photo))
         .layer(DefaultBodyLimit::max(usize::MAX))
         .with_state(Arc::clone(&shared_state))
         .nest_service("/assets", tower_http::services::ServeDir::new("assets"));
@@ -135,23 +304,61 @@ async fn serve_index() -> Response {
 }
 
 #[tracing::instrument]
-async fn serve_search() -> Html<String> {
+async fn serve_search(State(state): State<Arc<AppState>>) -> Html<String> {
+    let tags = state.db(|db| db.get_tags()).await.unwrap_or_default();
+
     Html(
         TEMPLATES
             .get_template("search.html")
             .unwrap()
-            .render(context!())
+            .render(context!(tags))
             .unwrap(),
     )
 }
 
+/// Split a comma separated tag filter, ignoring blanks
+fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Split comma separated `key=value` attribute filters, ignoring malformed ones
+fn parse_attribute_filters(filters: &str) -> Vec<(String, String)> {
+    filters
+        .split(',')
+        .filter_map(database::parse_filter)
+        .collect()
+}
+
 #[tracing::instrument]
 async fn search(
     State(state): State<Arc<AppState>>,
     Form(query): Form<HashMap<String, String>>,
 ) -> Html<String> {
-    let results = if let Some(query) = query.get("search") {
-        match state.database.query(query) {
+    let options = database::SearchOptions {
+        min_similarity: query
+            .get("min_relevance")
+            .and_then(|relevance| relevance.parse::<f64>().ok())
+            .map(|relevance| relevance / 100.0)
+            .unwrap_or_default(),
+        container_id: query
+            .get("container")
+            .and_then(|container_id| container_id.parse::<i64>().ok()),
+        tags: query
+            .get("tags")
+            .map(|tags| parse_tags(tags))
+            .unwrap_or_default(),
+        attributes: query
+            .get("attributes")
+            .map(|attributes| parse_attribute_filters(attributes))
+            .unwrap_or_default(),
+    };
+
+    let results = if let Some(search) = query.get("search").cloned() {
+        let options = options.clone();
+        match state.db(move |db| db.query(&search, &options)).await {
             Ok(results) => results,
             Err(e) => {
                 error!("{}", e);
@@ -162,472 +369,1326 @@ async fn search(
         Vec::new()
     };
 
+    // containers are matched on their name and location text, so filters for items don't apply
+    let containers = match query.get("search") {
+        Some(search) if options.tags.is_empty() && options.attributes.is_empty() => {
+            let search = search.clone();
+            state
+                .db(move |db| db.query_containers(&search, &options))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("{}", e);
+                    Vec::new()
+                })
+        }
+        _ => Vec::new(),
+    };
+
     Html(
         TEMPLATES
             .get_template("search.html")
             .unwrap()
-            .eval_to_state(context!(results))
+            .render_captured(context!(results, containers))
             .unwrap()
-            .render_block("query_results")
+            .with_state_mut(|state| state.render_block("query_results"))
             .unwrap(),
     )
 }
 
-async fn small_photo(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<i64>,
-) -> Result<Bytes, StatusCode> {
-    match state.database.get_small_image(id) {
-        Ok(image) => Ok(image.into()),
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    }
-}
-
-async fn large_photo(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<i64>,
-) -> Result<Bytes, StatusCode> {
-    match state.database.get_large_image(id) {
-        Ok(image) => Ok(image.into()),
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    }
+#[tracing::instrument]
+async fn serve_admin(State(state): State<Arc<AppState>>) -> Html<String> {
+    Html(
+        TEMPLATES
+            .get_template("admin.html")
+            .unwrap()
+            .render(context!(
+                embedding_model => state.database.active_embedding_model(),
+                reindex => state.reindexer.status()
+            ))
+            .unwrap(),
+    )
 }
 
 #[tracing::instrument]
-async fn container(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Html<String> {
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
-    };
-
-    let Ok(items) = state.database.get_container_items(id) else {
-        return Html(String::from("Failed to retrieve items"));
+async fn serve_duplicates(State(state): State<Arc<AppState>>) -> Html<String> {
+    let Ok(duplicates) = state.db(|db| db.get_possible_duplicates()).await else {
+        return Html(String::from("Failed to retrieve possible duplicates"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("duplicates.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => id))
+            .render(context!(duplicates))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn container_create_child(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<i64>,
-) -> Html<String> {
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
+async fn serve_history(State(state): State<Arc<AppState>>) -> Html<String> {
+    let Ok(history) = state
+        .db(move |db| db.get_recent_changes(RECENT_CHANGES_LIMIT))
+        .await
+    else {
+        return Html(String::from("Failed to retrieve history"));
     };
 
-    let Ok(items) = state.database.get_container_items(id) else {
-        return Html(String::from("Failed to retrieve items"));
+    Html(
+        TEMPLATES
+            .get_template("history.html")
+            .unwrap()
+            .render(context!(history))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn serve_trash(State(state): State<Arc<AppState>>) -> Html<String> {
+    let Ok(trash) = state.db(|db| db.get_trash()).await else {
+        return Html(String::from("Failed to retrieve trash"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("trash.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => id, add_child => true))
+            .render(context!(trash, retention_days => state.trash_retention_days))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn create_container(
+async fn restore_trash(
     State(state): State<Arc<AppState>>,
-    Form(payload): Form<CreateContainer>,
+    Path(trash_id): Path<i64>,
+) -> Html<String> {
+    if let Err(e) = state.db(move |db| db.restore_trash(trash_id)).await {
+        error!("{}", e);
+        return Html(String::from("Failed to restore"));
+    }
+
+    serve_trash(State(state)).await
+}
+
+#[tracing::instrument]
+async fn purge_trash(
+    State(state): State<Arc<AppState>>,
+    Path(trash_id): Path<i64>,
+) -> Html<String> {
+    if let Err(e) = state.db(move |db| db.purge_trash_entry(trash_id)).await {
+        error!("{}", e);
+        return Html(String::from("Failed to delete"));
+    }
+
+    serve_trash(State(state)).await
+}
+
+#[tracing::instrument]
+async fn dismiss_duplicate(
+    State(state): State<Arc<AppState>>,
+    Path(duplicate_id): Path<i64>,
 ) -> Html<String> {
     state
-        .database
-        .add_child_container(&payload.new_container_name, payload.parent_container_id)
+        .db(move |db| db.dismiss_possible_duplicate(duplicate_id))
+        .await
         .unwrap();
 
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
-    };
+    serve_duplicates(State(state)).await
+}
 
-    let Ok(items) = state
-        .database
-        .get_container_items(payload.parent_container_id)
-    else {
-        return Html(String::from("Failed to retrieve items"));
-    };
+#[tracing::instrument]
+async fn start_reindex(State(state): State<Arc<AppState>>) -> Html<String> {
+    if let Err(e) = state.reindexer.start() {
+        error!("{}", e);
+    }
 
+    reindex_status(State(state)).await
+}
+
+#[tracing::instrument]
+async fn reindex_status(State(state): State<Arc<AppState>>) -> Html<String> {
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("admin.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => payload.parent_container_id))
+            .render_captured(context!(
+                embedding_model => state.database.active_embedding_model(),
+                reindex => state.reindexer.status()
+            ))
+            .unwrap()
+            .with_state_mut(|state| state.render_block("reindex_status"))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn get_container_rename(
-    State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
-) -> Html<String> {
-    let Ok(container_name) = state.database.get_container_name(container_id) else {
-        return Html(String::from("Failed to fetch container name"));
+async fn check_database(State(state): State<Arc<AppState>>) -> Html<String> {
+    let Ok(check) = state.db(|db| db.check()).await else {
+        return Html(String::from("Failed to check database"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/container_edit.html")
+            .get_template("admin.html")
             .unwrap()
-            .render(context!(container_name, container_id))
+            .render_captured(context!(clean => check.is_clean(), check))
+            .unwrap()
+            .with_state_mut(|state| state.render_block("check_report"))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn get_container_rename_cancel(
+async fn repair_database(
     State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
+    Path(kind): Path<String>,
 ) -> Html<String> {
-    let Ok(container_name) = state.database.get_container_name(container_id) else {
-        return Html(String::from("Failed to fetch container name"));
+    let Ok(repair) = database::Repair::from_name(&kind) else {
+        return Html(String::from("Unknown repair"));
     };
 
-    Html(
-        TEMPLATES
-            .get_template("containers/container_single.html")
-            .unwrap()
-            .render(context!(container_name, container_id))
-            .unwrap(),
+    if let Err(e) = state.db(move |db| db.repair(repair)).await {
+        error!("{}", e);
+        return Html(String::from("Failed to repair database"));
+    }
+
+    check_database(State(state)).await
+}
+
+#[tracing::instrument]
+async fn export_backup(State(state): State<Arc<AppState>>) -> Response {
+    let backup = state
+        .db(|db| {
+            let mut file = tempfile::tempfile()?;
+            db.export_backup(&mut file)?;
+            file.rewind()?;
+            Ok(file)
+        })
+        .await;
+    let file = match backup {
+        Ok(file) => file,
+        Err(e) => {
+            error!("{}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export backup").into_response();
+        }
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"stuff-search-backup.zip\"",
+            ),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(
+            tokio::fs::File::from_std(file),
+        )),
     )
+        .into_response()
 }
 
 #[tracing::instrument]
-async fn handle_container_rename(
+async fn restore_backup(
     State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
-    Form(new_container_name): Form<HashMap<String, String>>,
+    mut multipart: Multipart,
 ) -> Html<String> {
-    state
-        .database
-        .set_container_name(
-            new_container_name.get("new_container_name").unwrap(),
-            container_id,
-        )
-        .unwrap();
-
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
+    let mut archive = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            archive = field.bytes().await.ok();
+        }
+    }
+    let Some(archive) = archive else {
+        return Html(String::from("No backup file uploaded"));
     };
 
-    let Ok(items) = state.database.get_container_items(container_id) else {
-        return Html(String::from("Failed to retrieve items"));
+    let restored = state
+        .db(move |db| db.restore_backup(std::io::Cursor::new(archive)))
+        .await;
+    let (restored, error) = match restored {
+        Ok(summary) => (Some(summary), None),
+        Err(e) => {
+            error!("{}", e);
+            (None, Some(e.to_string()))
+        }
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("admin.html")
             .unwrap()
-            .render(
-                context!(container => containers, results => items, active_node_id => container_id),
-            )
+            .render_captured(context!(restored, error))
+            .unwrap()
+            .with_state_mut(|state| state.render_block("backup"))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn modal_upload(
-    State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
-) -> Html<String> {
-    let Ok(container_name) = state.database.get_container_name(container_id) else {
-        return Html(String::from("Failed to retrieve container"));
+async fn export_csv(State(state): State<Arc<AppState>>) -> Response {
+    match state.db(|db| db.export_csv()).await {
+        Ok(csv) => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"items.csv\"",
+                ),
+            ],
+            csv,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export items").into_response()
+        }
+    }
+}
+
+#[tracing::instrument]
+async fn import_csv(State(state): State<Arc<AppState>>, mut multipart: Multipart) -> Html<String> {
+    let mut csv = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            csv = field.text().await.ok();
+        }
+    }
+    let Some(csv) = csv else {
+        return Html(String::from("No CSV file uploaded"));
+    };
+
+    let (imported, error) = match state.db(move |db| db.import_csv(&csv)).await {
+        Ok(summary) => (Some(summary), None),
+        Err(e) => {
+            error!("{}", e);
+            (None, Some(e.to_string()))
+        }
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/modal_upload.html")
+            .get_template("admin.html")
             .unwrap()
-            .render(context!(container_name, container_id, in_progress => false))
+            .render_captured(context!(imported, error))
+            .unwrap()
+            .with_state_mut(|state| state.render_block("csv_import"))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn upload(State(state): State<Arc<AppState>>, mut multipart: Multipart) -> Html<String> {
-    let mut container_id = None;
-    let mut file = None;
-    let mut file_name = None;
+async fn search_image(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Html<String> {
+    let mut photo = None;
+    let mut options = database::SearchOptions::default();
 
     while let Ok(Some(field)) = multipart.next_field().await {
         let field_name = field.name().unwrap_or_default();
 
         match field_name {
             "file" => {
-                file_name = field.file_name().map(|s| s.to_string());
                 if let Ok(bytes) = field.bytes().await {
-                    let mut tmpfile = tempfile::tempfile().unwrap();
-                    tmpfile.write_all(bytes.as_bytes()).unwrap();
-                    tmpfile.seek(std::io::SeekFrom::Start(0)).unwrap();
-                    file = Some(tmpfile);
+                    photo = Some(bytes);
+                }
+            }
+            "min_relevance" => {
+                if let Ok(relevance) = field.text().await {
+                    if let Ok(relevance) = relevance.parse::<f64>() {
+                        options.min_similarity = relevance / 100.0;
+                    }
                 }
             }
             "container" => {
                 if let Ok(text) = field.text().await {
                     if let Ok(id) = text.parse::<i64>() {
-                        container_id = Some(id)
+                        options.container_id = Some(id)
                     }
                 }
             }
+            "tags" => {
+                if let Ok(text) = field.text().await {
+                    options.tags = parse_tags(&text);
+                }
+            }
+            "attributes" => {
+                if let Ok(text) = field.text().await {
+                    options.attributes = parse_attribute_filters(&text);
+                }
+            }
             _ => (),
         }
     }
 
-    if let (Some(container_id), Some(file)) = (container_id, file) {
-        let Ok(container_name) = state.database.get_container_name(container_id) else {
-            return Html(String::from("Failed to retrieve container"));
-        };
-        if state
-            .importer
-            .lock()
-            .unwrap()
-            .add_to_queue(import::ImportRequest {
-                source: file_name.unwrap_or(String::from("Unknown Filename")),
-                file,
-                target_container: container_id,
-            })
-            .is_err()
-        {
-            return Html(String::from("Failed to upload file to queue"));
+    let results = if let Some(photo) = photo {
+        match state.db(move |db| db.query_image(&photo, &options)).await {
+            Ok(results) => results,
+            Err(e) => {
+                error!("{}", e);
+                Vec::new()
+            }
         }
-
-        return Html(
-            TEMPLATES
-                .get_template("containers/modal_upload.html")
-                .unwrap()
-                .render(context!(container_name, container_id, in_progress => true))
-                .unwrap(),
-        );
-    }
-
-    Html(String::from(
-        "<script>bootstrap.Modal.getInstance(document.getElementById('modals-here')).hide()</script>",
-    ))
-}
-
-#[tracing::instrument]
-async fn modal_item_show(
-    State(state): State<Arc<AppState>>,
-    Path(item_id): Path<i64>,
-) -> Html<String> {
-    let Ok(item) = state.database.get_item(item_id) else {
-        return Html(String::from("Failed to retrieve item"));
+    } else {
+        Vec::new()
     };
 
     Html(
         TEMPLATES
-            .get_template("items/modal_display.html")
+            .get_template("search.html")
+            .unwrap()
+            .render_captured(context!(results))
             .unwrap()
-            .render(context!(item_id => item.id, item_name => item.name, item_location => item.container_name, item_description => item.description))
+            .with_state_mut(|state| state.render_block("query_results"))
             .unwrap(),
     )
 }
 
-#[tracing::instrument]
-async fn get_modal_item_edit(
+async fn small_photo(
     State(state): State<Arc<AppState>>,
-    Path(item_id): Path<i64>,
-) -> Html<String> {
-    let Ok(item) = state.database.get_item(item_id) else {
-        return Html(String::from("Failed to retrieve item"));
-    };
-
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    serve_primary_photo(state.db(move |db| db.get_small_image(id)).await, &headers).await
+}
+
+async fn large_photo(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    serve_primary_photo(state.db(move |db| db.get_large_image(id)).await, &headers).await
+}
+
+/// Items without a photo get the placeholder. It's a temporary redirect, so the photo
+/// shows up once one is added.
+async fn serve_primary_photo(
+    photo: Result<Option<database::Photo>>,
+    headers: &HeaderMap,
+) -> Response {
+    match photo {
+        Ok(None) => Redirect::temporary(NO_PHOTO_PLACEHOLDER).into_response(),
+        photo => {
+            serve_photo(
+                photo.map(Option::unwrap),
+                PRIMARY_PHOTO_CACHE_CONTROL,
+                headers,
+            )
+            .await
+        }
+    }
+}
+
+async fn small_item_photo(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    serve_photo(
+        state
+            .db(move |db| db.get_item_photo(id, database::PhotoSize::Small))
+            .await,
+        PHOTO_CACHE_CONTROL,
+        &headers,
+    )
+    .await
+}
+
+async fn large_item_photo(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    serve_photo(
+        state
+            .db(move |db| db.get_item_photo(id, database::PhotoSize::Large))
+            .await,
+        PHOTO_CACHE_CONTROL,
+        &headers,
+    )
+    .await
+}
+
+/// Stream a photo from the photo store, using its hash as the ETag
+async fn serve_photo(
+    photo: Result<database::Photo>,
+    cache_control: &'static str,
+    headers: &HeaderMap,
+) -> Response {
+    let Ok(photo) = photo else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = format!("\"{}\"", photo.hash);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, String::from(cache_control)),
+    ];
+    let cached = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if cached {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    let Ok(file) = tokio::fs::File::open(&photo.path).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    (
+        cache_headers,
+        [(header::CONTENT_TYPE, "image/jpeg")],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+#[tracing::instrument]
+async fn container(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(listing): Query<ContainerListing>,
+) -> Html<String> {
+    let Ok(sort) = listing.sort() else {
+        return Html(String::from("Unknown sort"));
+    };
+
+    render_container_page(&state, id, sort, listing.recursive, false).await
+}
+
+/// The next page of a container's items, requested as the grid is scrolled to its end
+#[tracing::instrument]
+async fn container_items(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(listing): Query<ContainerListing>,
+) -> Html<String> {
+    let Ok(sort) = listing.sort() else {
+        return Html(String::from("Unknown sort"));
+    };
+    let Ok((items, next_offset)) =
+        container_items_page(&state, id, sort, listing.recursive, listing.offset).await
+    else {
+        return Html(String::from("Failed to retrieve items"));
+    };
+
     Html(
         TEMPLATES
-            .get_template("items/modal_edit.html")
+            .get_template("containers/containers.html")
             .unwrap()
-            .render(context!(item_id => item.id, item_name => item.name, item_location => item.container_name, item_description => item.description))
+            .render_captured(context!(results => items, active_node_id => id, sort => sort.as_str(), recursive => listing.recursive, next_offset))
+            .unwrap()
+            .with_state_mut(|state| state.render_block("container_items"))
             .unwrap(),
     )
 }
 
+async fn render_container_page(
+    state: &AppState,
+    id: i64,
+    sort: database::ItemSort,
+    recursive: bool,
+    add_child: bool,
+) -> Html<String> {
+    let Ok(containers) = state.db(|db| db.get_container_tree()).await else {
+        return Html(String::from("Failed to retrieve containers"));
+    };
+
+    let Ok((items, next_offset)) = container_items_page(state, id, sort, recursive, 0).await else {
+        return Html(String::from("Failed to retrieve items"));
+    };
+
+    Html(
+        TEMPLATES
+            .get_template("containers/containers.html")
+            .unwrap()
+            .render(context!(container => containers, results => items, active_node_id => id, add_child, sort => sort.as_str(), recursive, next_offset))
+            .unwrap(),
+    )
+}
+
+/// One page of items, plus the offset of the next page if there is one
+async fn container_items_page(
+    state: &AppState,
+    id: i64,
+    sort: database::ItemSort,
+    recursive: bool,
+    offset: i64,
+) -> Result<(Vec<database::ItemResult>, Option<i64>)> {
+    // one extra tells whether another page follows
+    let mut items = state
+        .db(move |db| db.get_container_items(id, recursive, sort, offset, CONTAINER_PAGE_SIZE + 1))
+        .await?;
+    let next_offset = if items.len() as i64 > CONTAINER_PAGE_SIZE {
+        items.truncate(CONTAINER_PAGE_SIZE as usize);
+        Some(offset + CONTAINER_PAGE_SIZE)
+    } else {
+        None
+    };
+
+    Ok((items, next_offset))
+}
+
 #[tracing::instrument]
-async fn handle_modal_item_edit(
+async fn container_create_child(
     State(state): State<Arc<AppState>>,
-    Path(item_id): Path<i64>,
-    Form(edit_item): Form<EditItem>,
+    Path(id): Path<i64>,
+) -> Html<String> {
+    render_container_page(&state, id, database::ItemSort::default(), false, true).await
+}
+
+#[tracing::instrument]
+async fn create_container(
+    State(state): State<Arc<AppState>>,
+    Form(payload): Form<CreateContainer>,
 ) -> Html<String> {
     state
-        .database
-        .update_item(item_id, &edit_item.new_name, &edit_item.new_description)
+        .db(move |db| {
+            db.add_child_container(&payload.new_container_name, payload.parent_container_id)
+        })
+        .await
         .unwrap();
 
+    render_container_page(
+        &state,
+        payload.parent_container_id,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+}
+
+#[tracing::instrument]
+async fn get_container_rename(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Html<String> {
+    let Ok(container_name) = state
+        .db(move |db| db.get_container_name(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to fetch container name"));
+    };
+    let Ok(container_location) = state
+        .db(move |db| db.get_container_location(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to fetch container location"));
+    };
+
     Html(
+        TEMPLATES
+            .get_template("containers/container_edit.html")
+            .unwrap()
+            .render(context!(container_name, container_location, container_id))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn get_container_rename_cancel(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Html<String> {
+    let Ok(container_name) = state
+        .db(move |db| db.get_container_name(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to fetch container name"));
+    };
+    let Ok(container_location) = state
+        .db(move |db| db.get_container_location(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to fetch container location"));
+    };
+
+    Html(
+        TEMPLATES
+            .get_template("containers/container_single.html")
+            .unwrap()
+            .render(context!(container_name, container_location, container_id))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn handle_container_rename(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+    Form(new_container_name): Form<HashMap<String, String>>,
+) -> Html<String> {
+    state
+        .db(move |db| {
+            db.set_container_name(
+                new_container_name.get("new_container_name").unwrap(),
+                container_id,
+            )?;
+            if let Some(location) = new_container_name.get("new_container_location") {
+                let location = Some(location.trim()).filter(|location| !location.is_empty());
+                db.set_container_location(location, container_id)?;
+            }
+
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+    render_container_page(
+        &state,
+        container_id,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+}
+
+#[tracing::instrument]
+async fn modal_upload(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Html<String> {
+    let Ok(container_name) = state
+        .db(move |db| db.get_container_name(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to retrieve container"));
+    };
+
+    Html(
+        TEMPLATES
+            .get_template("containers/modal_upload.html")
+            .unwrap()
+            .render(context!(container_name, container_id, in_progress => false))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn upload(State(state): State<Arc<AppState>>, mut multipart: Multipart) -> Html<String> {
+    let mut container_id = None;
+    let mut files = Vec::new();
+    let mut file_names = Vec::new();
+    let mut single_item = false;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let field_name = field.name().unwrap_or_default();
+
+        match field_name {
+            "file" => {
+                if let Some(file_name) = field.file_name() {
+                    file_names.push(file_name.to_string());
+                }
+                if let Ok(bytes) = field.bytes().await {
+                    let mut tmpfile = tempfile::tempfile().unwrap();
+                    tmpfile.write_all(bytes.as_bytes()).unwrap();
+                    tmpfile.seek(std::io::SeekFrom::Start(0)).unwrap();
+                    files.push(tmpfile);
+                }
+            }
+            "single_item" => single_item = true,
+            "container" => {
+                if let Ok(text) = field.text().await {
+                    if let Ok(id) = text.parse::<i64>() {
+                        container_id = Some(id)
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if let (Some(container_id), false) = (container_id, files.is_empty()) {
+        let Ok(container_name) = state
+            .db(move |db| db.get_container_name(container_id))
+            .await
+        else {
+            return Html(String::from("Failed to retrieve container"));
+        };
+        let source = if file_names.is_empty() {
+            String::from("Unknown Filename")
+        } else {
+            file_names.join(", ")
+        };
+        let Ok(log_id) = state
+            .db(move |db| db.log_new_import(&source, "Added to queue", container_id))
+            .await
+        else {
+            return Html(String::from("Failed to upload file to queue"));
+        };
+        if state
+            .importer
+            .lock()
+            .unwrap()
+            .add_to_queue(
+                log_id,
+                import::ImportRequest {
+                    files,
+                    target_container: container_id,
+                    single_item,
+                },
+            )
+            .is_err()
+        {
+            return Html(String::from("Failed to upload file to queue"));
+        }
+
+        return Html(
             TEMPLATES
-                .get_template("items/modal_display.html")
+                .get_template("containers/modal_upload.html")
                 .unwrap()
-                .render(context!(item_id, item_name => edit_item.new_name, item_location => edit_item.new_location, item_description => edit_item.new_description))
+                .render(context!(container_name, container_id, in_progress => true))
                 .unwrap(),
-        )
+        );
+    }
+
+    Html(String::from(
+        "<script>bootstrap.Modal.getInstance(document.getElementById('modals-here')).hide()</script>",
+    ))
 }
 
 #[tracing::instrument]
-async fn delete_item_unconfirmed(Path(item_id): Path<i64>) -> Html<String> {
+async fn modal_new_item(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Html<String> {
+    let Ok(container_name) = state
+        .db(move |db| db.get_container_name(container_id))
+        .await
+    else {
+        return Html(String::from("Failed to retrieve container"));
+    };
+
     Html(
         TEMPLATES
-            .get_template("items/delete_confirm_snippet.html")
+            .get_template("items/modal_new.html")
             .unwrap()
-            .render(context!(item_id))
+            .render(context!(container_name, container_id))
             .unwrap(),
     )
 }
 
+/// Add an item by hand, without a photo or the vision model
 #[tracing::instrument]
-async fn delete_item(State(state): State<Arc<AppState>>, Path(item_id): Path<i64>) -> Html<String> {
-    // get item container
-    let container_id = state.database.get_item(item_id).unwrap().container_id;
+async fn create_item(
+    State(state): State<Arc<AppState>>,
+    Form(new_item): Form<CreateItem>,
+) -> Response {
+    let name = new_item.name.trim().to_string();
+    if name.is_empty() {
+        return Html(String::from("Name is required")).into_response();
+    }
+    let description: Vec<String> = new_item
+        .description
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+
+    let created = state
+        .db(move |db| {
+            db.insert_item(
+                &name,
+                &description,
+                &[],
+                new_item.container_id,
+                &database::ItemDetails::default(),
+            )
+        })
+        .await;
+    let item_id = match created {
+        Ok(item_id) => item_id,
+        Err(e) if e.is::<database::ContainerUnavailable>() => {
+            return (StatusCode::BAD_REQUEST, Html(e.to_string())).into_response();
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Html(String::from("Failed to create item")).into_response();
+        }
+    };
 
-    // do deletion
-    state.database.delete_item(item_id).unwrap();
+    modal_item_show(State(state), Path(item_id))
+        .await
+        .into_response()
+}
 
-    // return relevant container page
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
+#[tracing::instrument]
+async fn modal_item_show(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
+) -> Html<String> {
+    let Ok(item) = state.db(move |db| db.get_item(item_id)).await else {
+        return Html(String::from("Failed to retrieve item"));
     };
+    let history = state
+        .db(move |db| db.get_item_history(item_id))
+        .await
+        .unwrap_or_default();
+    let item_photos = state
+        .db(move |db| db.get_item_photos(item_id))
+        .await
+        .unwrap_or_default();
 
-    let Ok(items) = state.database.get_container_items(container_id) else {
-        return Html(String::from("Failed to retrieve items"));
+    Html(
+        TEMPLATES
+            .get_template("items/modal_display.html")
+            .unwrap()
+            .render(context!(item_id => item.id, item_name => item.name, item_location => item.container_name, item_container_path => item.container_path, item_container_location => item.container_location, item_description => item.description, item_quantity => item.quantity, item_unit => item.unit, item_tags => item.tags, item_attributes => item.attributes, item_photos, history))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn get_modal_item_edit(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
+) -> Html<String> {
+    let Ok(item) = state.db(move |db| db.get_item(item_id)).await else {
+        return Html(String::from("Failed to retrieve item"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("items/modal_edit.html")
             .unwrap()
-            .render(
-                context!(container => containers, results => items, active_node_id => container_id),
-            )
+            .render(context!(item_id => item.id, item_primary_photo_id => item.primary_photo_id, item_name => item.name, item_location => item.container_name, item_container_path => item.container_path, item_container_location => item.container_location, item_description => item.description, item_quantity => item.quantity, item_unit => item.unit, item_tags => item.tags, item_attributes => item.attributes))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn delete_container_unconfirmed(
+async fn handle_modal_item_edit(
     State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
+    Path(item_id): Path<i64>,
+    Form(edit_item): Form<EditItem>,
+) -> Html<String> {
+    state
+        .db(move |db| {
+            let item_unit = Some(edit_item.new_unit.trim()).filter(|unit| !unit.is_empty());
+            db.update_item(
+                item_id,
+                &edit_item.new_name,
+                &edit_item.new_description,
+                edit_item.new_quantity,
+                item_unit,
+            )
+        })
+        .await
+        .unwrap();
+
+    modal_item_show(State(state), Path(item_id)).await
+}
+
+#[tracing::instrument]
+async fn increment_item_quantity(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
+) -> Html<String> {
+    render_item_quantity(&state, item_id, 1).await
+}
+
+#[tracing::instrument]
+async fn decrement_item_quantity(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
 ) -> Html<String> {
-    let container_name = state.database.get_container_name(container_id).unwrap();
+    render_item_quantity(&state, item_id, -1).await
+}
+
+async fn render_item_quantity(state: &AppState, item_id: i64, delta: i64) -> Html<String> {
+    if state
+        .db(move |db| db.adjust_item_quantity(item_id, delta))
+        .await
+        .is_err()
+    {
+        return Html(String::from("Failed to update quantity"));
+    }
+    let Ok(item) = state.db(move |db| db.get_item(item_id)).await else {
+        return Html(String::from("Failed to retrieve item"));
+    };
 
     Html(
         TEMPLATES
-            .get_template("containers/modal_delete.html")
+            .get_template("items/quantity_snippet.html")
             .unwrap()
-            .render(context!(container_id, container_name))
+            .render(context!(item_id, item_quantity => item.quantity, item_unit => item.unit))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn delete_container(
+async fn add_item_tag(
     State(state): State<Arc<AppState>>,
-    Path(container_id): Path<i64>,
+    Path(item_id): Path<i64>,
+    Form(form): Form<HashMap<String, String>>,
 ) -> Html<String> {
-    let container_parent = state.database.get_container_parent(container_id).unwrap();
+    if let Some(tag) = form.get("tag").cloned() {
+        if let Err(e) = state.db(move |db| db.add_item_tag(item_id, &tag)).await {
+            error!("{}", e);
+        }
+    }
 
-    state.database.delete_container(container_id).unwrap();
+    render_item_tags(&state, item_id).await
+}
 
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
-    };
+#[tracing::instrument]
+async fn remove_item_tag(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Html<String> {
+    if let Some(tag) = form.get("tag").cloned() {
+        if state
+            .db(move |db| db.remove_item_tag(item_id, &tag))
+            .await
+            .is_err()
+        {
+            return Html(String::from("Failed to remove tag"));
+        }
+    }
 
-    let Ok(items) = state.database.get_container_items(container_parent) else {
-        return Html(String::from("Failed to retrieve items"));
+    render_item_tags(&state, item_id).await
+}
+
+async fn render_item_tags(state: &AppState, item_id: i64) -> Html<String> {
+    let Ok(item_tags) = state.db(move |db| db.get_item_tags(item_id)).await else {
+        return Html(String::from("Failed to retrieve tags"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("items/tags_snippet.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => container_parent))
+            .render(context!(item_id, item_tags))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn move_item(
+async fn set_item_attribute(
     State(state): State<Arc<AppState>>,
-    Path((item_id, container_id)): Path<(i64, i64)>,
+    Path(item_id): Path<i64>,
+    Form(form): Form<HashMap<String, String>>,
 ) -> Html<String> {
-    let current_container = state.database.get_item(item_id).unwrap().container_id;
+    if let (Some(key), Some(value)) = (form.get("key"), form.get("value")) {
+        let value_type = database::AttributeType::from_name(
+            form.get("value_type")
+                .map(String::as_str)
+                .unwrap_or_default(),
+        );
+        let attribute = database::Attribute::new(key, value, value_type);
+        if let Err(e) = state
+            .db(move |db| db.set_item_attribute(item_id, &attribute))
+            .await
+        {
+            error!("{}", e);
+        }
+    }
 
-    if container_id != 1 {
-        state.database.move_item(item_id, container_id).unwrap();
+    render_item_attributes(&state, item_id).await
+}
+
+#[tracing::instrument]
+async fn remove_item_attribute(
+    State(state): State<Arc<AppState>>,
+    Path(item_id): Path<i64>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Html<String> {
+    if let Some(key) = form.get("key").cloned() {
+        if state
+            .db(move |db| db.remove_item_attribute(item_id, &key))
+            .await
+            .is_err()
+        {
+            return Html(String::from("Failed to remove attribute"));
+        }
     }
 
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
-    };
+    render_item_attributes(&state, item_id).await
+}
 
-    let Ok(items) = state.database.get_container_items(current_container) else {
-        return Html(String::from("Failed to retrieve items"));
+async fn render_item_attributes(state: &AppState, item_id: i64) -> Html<String> {
+    let Ok(item_attributes) = state.db(move |db| db.get_item_attributes(item_id)).await else {
+        return Html(String::from("Failed to retrieve attributes"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("items/attributes_snippet.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => current_container))
+            .render(context!(item_id, item_attributes))
             .unwrap(),
     )
 }
 
 #[tracing::instrument]
-async fn move_container(
+async fn add_item_photos(
     State(state): State<Arc<AppState>>,
-    Path((container_source_id, container_target_id)): Path<(i64, i64)>,
+    Path(item_id): Path<i64>,
+    mut multipart: Multipart,
 ) -> Html<String> {
-    if container_source_id != container_target_id {
-        let parent = state
-            .database
-            .get_container_parent(container_source_id)
-            .unwrap();
-
-        // check if target is child of this one
-        let mut is_child = false;
-        let mut children = state
-            .database
-            .get_container_children(container_source_id)
-            .unwrap();
-        while let Some(child) = children.pop() {
-            if child == container_target_id {
-                is_child = true;
-                break;
+    let mut files = Vec::new();
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            if let Ok(bytes) = field.bytes().await {
+                files.push(bytes);
             }
-            children.extend(state.database.get_container_children(child).unwrap());
         }
+    }
 
-        if is_child {
-            state
-                .database
-                .move_container(container_target_id, parent)
-                .unwrap();
-        }
-        state
-            .database
-            .move_container(container_source_id, container_target_id)
-            .unwrap();
+    let photos = tokio::task::spawn_blocking(move || {
+        files
+            .into_iter()
+            .filter_map(|bytes| {
+                let mut tmpfile = tempfile::tempfile().ok()?;
+                tmpfile.write_all(&bytes).ok()?;
+                import::prepare_photo(tmpfile)
+                    .inspect_err(|e| warn!("Skipping photo: {}", e))
+                    .ok()
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    if let Err(e) = state
+        .db(move |db| db.add_item_photos(item_id, &photos))
+        .await
+    {
+        error!("{}", e);
+        return Html(String::from("Failed to add photos"));
     }
 
-    let Ok(containers) = state.database.get_container_tree() else {
-        return Html(String::from("Failed to retrieve containers"));
-    };
+    render_item_photos(&state, item_id).await
+}
 
-    let Ok(items) = state.database.get_container_items(container_source_id) else {
-        return Html(String::from("Failed to retrieve items"));
+#[tracing::instrument]
+async fn set_primary_item_photo(
+    State(state): State<Arc<AppState>>,
+    Path((item_id, photo_id)): Path<(i64, i64)>,
+) -> Html<String> {
+    if let Err(e) = state
+        .db(move |db| db.set_primary_photo(item_id, photo_id))
+        .await
+    {
+        error!("{}", e);
+        return Html(String::from("Failed to set primary photo"));
+    }
+
+    render_item_photos(&state, item_id).await
+}
+
+#[tracing::instrument]
+async fn remove_item_photo(
+    State(state): State<Arc<AppState>>,
+    Path((item_id, photo_id)): Path<(i64, i64)>,
+) -> Html<String> {
+    if let Err(e) = state
+        .db(move |db| db.remove_item_photo(item_id, photo_id))
+        .await
+    {
+        error!("{}", e);
+        return Html(String::from("Failed to remove photo"));
+    }
+
+    render_item_photos(&state, item_id).await
+}
+
+async fn render_item_photos(state: &AppState, item_id: i64) -> Html<String> {
+    let Ok(item_photos) = state.db(move |db| db.get_item_photos(item_id)).await else {
+        return Html(String::from("Failed to retrieve photos"));
     };
 
     Html(
         TEMPLATES
-            .get_template("containers/containers.html")
+            .get_template("items/photos_snippet.html")
+            .unwrap()
+            .render(context!(item_id, item_photos))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn delete_item_unconfirmed(Path(item_id): Path<i64>) -> Html<String> {
+    Html(
+        TEMPLATES
+            .get_template("items/delete_confirm_snippet.html")
+            .unwrap()
+            .render(context!(item_id))
+            .unwrap(),
+    )
+}
+
+#[tracing::instrument]
+async fn delete_item(State(state): State<Arc<AppState>>, Path(item_id): Path<i64>) -> Html<String> {
+    // get item container
+    let container_id = state
+        .db(move |db| db.get_item(item_id))
+        .await
+        .unwrap()
+        .container_id;
+
+    // do deletion
+    state.db(move |db| db.delete_item(item_id)).await.unwrap();
+
+    // return relevant container page
+    render_container_page(
+        &state,
+        container_id,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+}
+
+#[tracing::instrument]
+async fn delete_container_unconfirmed(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Html<String> {
+    let container_name = state
+        .db(move |db| db.get_container_name(container_id))
+        .await
+        .unwrap();
+
+    Html(
+        TEMPLATES
+            .get_template("containers/modal_delete.html")
             .unwrap()
-            .render(context!(container => containers, results => items, active_node_id => container_source_id))
+            .render(context!(container_id, container_name))
             .unwrap(),
     )
 }
+
+#[tracing::instrument]
+async fn delete_container(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<i64>,
+) -> Response {
+    if container_id == 1 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(String::from("The ROOT container can't be deleted")),
+        )
+            .into_response();
+    }
+    let Ok(container_parent) = state
+        .db(move |db| db.get_container_parent(container_id))
+        .await
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Html(String::from("Failed to retrieve container")),
+        )
+            .into_response();
+    };
+
+    if let Err(e) = state.db(move |db| db.delete_container(container_id)).await {
+        error!("{}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Html(String::from("Failed to delete container")),
+        )
+            .into_response();
+    }
+
+    render_container_page(
+        &state,
+        container_parent,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+    .into_response()
+}
+
+#[tracing::instrument]
+async fn move_item(
+    State(state): State<Arc<AppState>>,
+    Path((item_id, container_id)): Path<(i64, i64)>,
+) -> Response {
+    let Ok(item) = state.db(move |db| db.get_item(item_id)).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Html(String::from("Failed to retrieve item")),
+        )
+            .into_response();
+    };
+
+    if container_id != 1 {
+        match state
+            .db(move |db| db.move_item(item_id, container_id))
+            .await
+        {
+            Ok(()) => (),
+            Err(e) if e.is::<database::ContainerUnavailable>() => {
+                return (StatusCode::BAD_REQUEST, Html(e.to_string())).into_response();
+            }
+            Err(e) => {
+                error!("{}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Html(String::from("Failed to move item")),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    render_container_page(
+        &state,
+        item.container_id,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+    .into_response()
+}
+
+#[tracing::instrument]
+async fn move_container(
+    State(state): State<Arc<AppState>>,
+    Path((container_source_id, container_target_id)): Path<(i64, i64)>,
+) -> Response {
+    match state
+        .db(move |db| db.move_container(container_source_id, container_target_id))
+        .await
+    {
+        Ok(()) => (),
+        Err(e)
+            if e.is::<database::ContainerUnavailable>() || e.is::<database::ContainerCycle>() =>
+        {
+            return (StatusCode::BAD_REQUEST, Html(e.to_string())).into_response();
+        }
+        Err(e) => {
+            error!("{}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html(String::from("Failed to move container")),
+            )
+                .into_response();
+        }
+    }
+
+    render_container_page(
+        &state,
+        container_source_id,
+        database::ItemSort::default(),
+        false,
+        false,
+    )
+    .await
+    .into_response()
+}
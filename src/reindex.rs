@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::database::Database;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexStatus {
+    pub running: bool,
+    pub done: usize,
+    pub total: usize,
+    pub error: Option<String>,
+}
+
+/// Runs re-embedding of the whole inventory in the background
+pub struct Reindexer {
+    db: Arc<Database>,
+    status: Arc<Mutex<ReindexStatus>>,
+}
+
+impl Reindexer {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self {
+            db,
+            status: Arc::new(Mutex::new(ReindexStatus::default())),
+        }
+    }
+
+    /// Re-index into the configured model in the background, resuming the pending index if
+    /// there is one. Failures end up in the status.
+    pub fn start(&self) -> Result<()> {
+        {
+            let mut status = self.status.lock().unwrap();
+            if status.running {
+                bail!("Re-index already running");
+            }
+            *status = ReindexStatus {
+                running: true,
+                ..Default::default()
+            };
+        }
+
+        let db = self.db.clone();
+        let status = self.status.clone();
+        // taking the index write lock can wait on a long write, so it stays off the runtime
+        tokio::task::spawn_blocking(move || {
+            let result = if db.reindex_pending() {
+                Ok(())
+            } else {
+                db.begin_reindex()
+            }
+            .and_then(|()| {
+                db.reindex(|done, total| {
+                    info!("Re-indexed {done} of {total} items");
+                    let mut status = status.lock().unwrap();
+                    status.done = done;
+                    status.total = total;
+                })
+            });
+
+            let mut status = status.lock().unwrap();
+            status.running = false;
+            if let Err(e) = result {
+                error!("Re-index failed: {e}");
+                status.error = Some(e.to_string());
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn status(&self) -> ReindexStatus {
+        self.status.lock().unwrap().clone()
+    }
+}